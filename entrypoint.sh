@@ -5,6 +5,29 @@ set -e
 OK=0
 ERR=1
 
+# print the kv-store password, unless tofnd runs with --no-password
+password_secret() {
+    if [ -z "${NOPASSWORD}" ]; then
+        echo ${PASSWORD}
+    fi
+}
+
+# secrets of commands that write $EXPORT_PATH: the password and, unless PLAINTEXT_EXPORT is set,
+# the export passphrase twice
+export_secrets() {
+    password_secret
+    if [ -z "${PLAINTEXT_EXPORT}" ]; then
+        echo ${EXPORT_PASSPHRASE}
+        echo ${EXPORT_PASSPHRASE}
+    fi
+}
+
+# secrets of import: the password and the export passphrase, in case $IMPORT_PATH is encrypted
+import_secrets() {
+    password_secret
+    echo ${EXPORT_PASSPHRASE}
+}
+
 # create: create a new mnemonic and export it to $EXPORT_PATH
 create_mnemonic() {
     echo "Creating mnemonic ..."
@@ -15,7 +38,7 @@ create_mnemonic() {
         return $ERR
     fi
 
-    (echo ${PASSWORD} | tofnd ${ARGS} -m create) && echo "... ok" && return $OK
+    (export_secrets | tofnd ${ARGS} ${EXPORT_ARGS} -m create) && echo "... ok" && return $OK
     return $ERR
 }
 
@@ -33,13 +56,7 @@ import_mnemonic() {
         return $ERR
     fi
 
-    if [ -n "${NOPASSWORD}" ]; then \
-        echo "No password"
-        ( cat $IMPORT_PATH | tofnd ${ARGS} -m import ) || return $ERR
-    else
-        echo "With password"
-        ( (echo $PASSWORD && cat $IMPORT_PATH) | tofnd ${ARGS} -m import ) || return $ERR
-    fi
+    (import_secrets | tofnd ${ARGS} -m import --import-file "$IMPORT_PATH") || return $ERR
 
     echo "... ok"
     return $OK
@@ -48,7 +65,7 @@ import_mnemonic() {
 # export: export the mnemonic to $EXPORT_PATH
 export_mnemonic() {
     echo "Exporting mnemonic ..."
-    echo ${PASSWORD} | tofnd ${ARGS} -m export || return $ERR
+    export_secrets | tofnd ${ARGS} ${EXPORT_ARGS} -m export || return $ERR
     echo "... ok"
     return $OK
 }
@@ -56,6 +73,8 @@ export_mnemonic() {
 # Get password from env var
 EMPTY_STRING=""
 PASSWORD="${PASSWORD:-$EMPTY_STRING}"
+# passphrase of the encrypted export file; required unless PLAINTEXT_EXPORT is set
+EXPORT_PASSPHRASE="${EXPORT_PASSPHRASE:-$EMPTY_STRING}"
 
 # set tofnd root. TOFND_HOME can be set to a different path by the user.
 TOFND_HOME=${TOFND_HOME:-"./.tofnd"}
@@ -72,6 +91,8 @@ ARGS=${NOPASSWORD:+"--no-password"}
 ARGS+=${ADDRESS:+" --address ${ADDRESS}"}
 # add '--port' flag to args if enabled
 ARGS+=${PORT:+" --port ${PORT}"}
+# add '--plaintext-export' flag to commands that write $EXPORT_PATH if enabled
+EXPORT_ARGS=${PLAINTEXT_EXPORT:+"--plaintext-export"}
 
 # check mnemonic arg
 if [ -n "${MNEMONIC_CMD}" ]; then \
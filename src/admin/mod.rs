@@ -0,0 +1,278 @@
+//! Admin gRPC service that manages the mnemonics of the running daemon.
+//!
+//! Mnemonic commands otherwise need a one-shot launch of tofnd, because sled locks the kv store
+//! directory. The admin service runs them against the live [KvManager] instead, so mnemonics can
+//! be rotated without stopping the `Multisig` service. It is only served on a local address, and
+//! requires the same bearer tokens as the `Multisig` service; on a TCP port, authentication is
+//! mandatory, since any local user can connect to it.
+
+use std::time::Instant;
+
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    auth::Authenticator,
+    config::AdminListener,
+    kv_manager::KvManager,
+    mnemonic::{Cmd, ExportMode},
+    proto::admin::{self, admin_server::AdminServer},
+    TofndResult,
+};
+
+// logging
+use tracing::{error, info};
+
+/// `AdminService` runs mnemonic commands against a live [KvManager]
+#[derive(Clone)]
+pub struct AdminService {
+    kv_manager: KvManager,
+    started: Instant,
+}
+
+impl AdminService {
+    pub fn new(kv_manager: KvManager) -> Self {
+        Self {
+            kv_manager,
+            started: Instant::now(),
+        }
+    }
+
+    async fn current_seed_count(&self) -> Result<u32, Status> {
+        self.kv_manager.seed_count().await.map_err(|err| {
+            error!("Unable to get the mnemonic count: {}", err);
+            Status::internal(err.to_string())
+        })
+    }
+
+    fn export_path(&self) -> String {
+        self.kv_manager.io().export_path().display().to_string()
+    }
+
+    /// Run a mnemonic command that writes the export file, encrypted under `export_passphrase`
+    /// if it is not empty
+    async fn handle_exporting_cmd(
+        &self,
+        cmd: Cmd,
+        export_passphrase: String,
+    ) -> Result<(), Status> {
+        let kv_manager = match export_passphrase.is_empty() {
+            true => self.kv_manager.clone(),
+            false => {
+                let export_mode = ExportMode::encrypted(export_passphrase)
+                    .map_err(|err| Status::invalid_argument(err.to_string()))?;
+                self.kv_manager.clone().with_export_mode(Some(export_mode))
+            }
+        };
+
+        // an existing export file would fail the command after the kv store is modified
+        kv_manager
+            .io()
+            .check_if_not_exported()
+            .map_err(|err| Status::failed_precondition(err.to_string()))?;
+        if !kv_manager.io().can_export() {
+            return Err(Status::failed_precondition(
+                "an export passphrase is required unless tofnd runs with --plaintext-export",
+            ));
+        }
+
+        kv_manager.handle_mnemonic(&cmd).await.map_err(|err| {
+            error!("Admin command <{:?}> failed: {}", cmd, err);
+            Status::internal(err.to_string())
+        })?;
+
+        info!("Admin command <{:?}> completed", cmd);
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl admin::admin_server::Admin for AdminService {
+    async fn rotate(
+        &self,
+        request: Request<admin::RotateRequest>,
+    ) -> Result<Response<admin::RotateResponse>, Status> {
+        self.handle_exporting_cmd(Cmd::Rotate, request.into_inner().export_passphrase)
+            .await?;
+
+        Ok(Response::new(admin::RotateResponse {
+            seed_count: self.current_seed_count().await?,
+            export_path: self.export_path(),
+        }))
+    }
+
+    async fn export(
+        &self,
+        request: Request<admin::ExportRequest>,
+    ) -> Result<Response<admin::ExportResponse>, Status> {
+        self.handle_exporting_cmd(Cmd::Export(None), request.into_inner().export_passphrase)
+            .await?;
+
+        Ok(Response::new(admin::ExportResponse {
+            export_path: self.export_path(),
+        }))
+    }
+
+    async fn seed_count(
+        &self,
+        _request: Request<admin::SeedCountRequest>,
+    ) -> Result<Response<admin::SeedCountResponse>, Status> {
+        Ok(Response::new(admin::SeedCountResponse {
+            seed_count: self.current_seed_count().await?,
+        }))
+    }
+
+    async fn status(
+        &self,
+        _request: Request<admin::StatusRequest>,
+    ) -> Result<Response<admin::StatusResponse>, Status> {
+        Ok(Response::new(admin::StatusResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            seed_count: self.current_seed_count().await?,
+            export_file_exists: self.kv_manager.io().check_if_not_exported().is_err(),
+            uptime_secs: self.started.elapsed().as_secs(),
+        }))
+    }
+}
+
+/// Bind `listener` and serve the admin service to clients accepted by `authenticator` in the
+/// background until `shutdown` fires
+pub async fn spawn(
+    listener: &AdminListener,
+    kv_manager: KvManager,
+    authenticator: Authenticator,
+    shutdown: oneshot::Receiver<()>,
+) -> TofndResult<JoinHandle<TofndResult<()>>> {
+    let router = tonic::transport::Server::builder().add_service(AdminServer::with_interceptor(
+        AdminService::new(kv_manager),
+        authenticator,
+    ));
+    let shutdown = async {
+        let _ = shutdown.await;
+    };
+
+    Ok(match listener {
+        AdminListener::Tcp(addr) => {
+            let incoming = TcpListener::bind(*addr).await?;
+            info!(
+                "tofnd admin service listen addr {:?}",
+                incoming.local_addr()?
+            );
+
+            tokio::spawn(async move {
+                router
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(incoming), shutdown)
+                    .await?;
+                Ok(())
+            })
+        }
+        AdminListener::Unix(unix_socket) => {
+            let (incoming, socket_file) = unix_socket.bind()?;
+            info!(
+                "tofnd admin service listen unix socket {:?}",
+                unix_socket.path
+            );
+
+            tokio::spawn(async move {
+                // the socket file is removed when `_socket_file` is dropped, also if serving fails
+                let _socket_file = socket_file;
+                router
+                    .serve_with_incoming_shutdown(UnixListenerStream::new(incoming), shutdown)
+                    .await?;
+                Ok(())
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addr,
+        encrypted_sled::get_test_password,
+        mnemonic::PhraseFormat,
+        proto::admin::admin_client::AdminClient,
+        tests::{DEFAULT_TEST_IP, DEFAULT_TEST_PORT},
+    };
+    use testdir::testdir;
+    use tracing_test::traced_test;
+
+    // key of the current mnemonic in the kv store
+    const MNEMONIC_KEY: &str = "mnemonic";
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_admin_rotate() {
+        let kv_manager = KvManager::new(testdir!(), get_test_password())
+            .unwrap()
+            .with_export_mode(Some(ExportMode::Plaintext))
+            .handle_mnemonic(&Cmd::Create(PhraseFormat::default()))
+            .await
+            .unwrap();
+        // back up and remove the export file of `Create`
+        std::fs::remove_file(kv_manager.io().export_path()).unwrap();
+        let old_mnemonic = kv_manager.kv().get(MNEMONIC_KEY).await.unwrap();
+
+        let incoming = TcpListener::bind(addr(DEFAULT_TEST_IP, DEFAULT_TEST_PORT).unwrap())
+            .await
+            .unwrap();
+        let server_addr = incoming.local_addr().unwrap();
+        let service = AdminServer::new(AdminService::new(kv_manager.clone()));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(incoming)),
+        );
+
+        let mut client = AdminClient::connect(format!("http://{}", server_addr))
+            .await
+            .unwrap();
+
+        let status = client
+            .status(admin::StatusRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.seed_count, 1);
+        assert!(!status.export_file_exists);
+
+        let rotated = client
+            .rotate(admin::RotateRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(rotated.seed_count, 2);
+        assert!(kv_manager.io().export_path().exists());
+
+        // new keygens use the new mnemonic right away
+        let new_mnemonic = kv_manager.kv().get(MNEMONIC_KEY).await.unwrap();
+        assert_ne!(old_mnemonic, new_mnemonic);
+
+        // the export file must be removed before the next rotate
+        let status = client
+            .rotate(admin::RotateRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        // exports are encrypted under the requested passphrase
+        std::fs::remove_file(kv_manager.io().export_path()).unwrap();
+        client
+            .export(admin::ExportRequest {
+                export_passphrase: "export passphrase".to_string(),
+            })
+            .await
+            .unwrap();
+        let export = std::fs::read_to_string(kv_manager.io().export_path()).unwrap();
+        assert!(export.contains("ciphertext"));
+
+        let seed_count = client
+            .seed_count(admin::SeedCountRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(seed_count.seed_count, 2);
+    }
+}
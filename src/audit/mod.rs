@@ -0,0 +1,538 @@
+//! Tamper-evident audit log of multisig requests.
+//!
+//! Every `KeyPresence`, `Keygen` and `Sign` request, and every `BatchSign` entry, is appended to
+//! `<tofnd_path>/audit.log` as one JSON record per line. Each record holds the SHA-256 hash of the
+//! previous one, so that editing, removing or reordering records breaks the chain. The sequence
+//! number and hash of the last record are also written to `audit.head`, so that removing records
+//! from the end of the log is detected as well.
+//!
+//! The chain proves the integrity of the log, not who wrote it: anyone who can write both files can
+//! rebuild a consistent chain. Ship the records to an external store to protect against that.
+//!
+//! Records are written by a dedicated thread, so that requests don't wait for the disk. A required
+//! log instead makes each request wait until its record is written, and fail if it can't be.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+use crate::{metrics::Rpc, proto::Algorithm};
+
+// error handling
+use crate::TofndResult;
+use anyhow::anyhow;
+
+/// name of the audit log inside the tofnd directory
+const AUDIT_LOG_FILE_NAME: &str = "audit.log";
+/// name of the file holding the sequence number and hash of the last record
+const AUDIT_HEAD_FILE_NAME: &str = "audit.head";
+
+/// `prev_hash` of the first record
+const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// records queued for the writer thread; requests wait once the queue is full
+const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+type RecordHash = [u8; 32];
+
+/// A request to be recorded in the audit log
+pub struct AuditEvent<'a> {
+    pub rpc: Rpc,
+    /// authentication label of the client, or `party_uid` if authentication is disabled
+    pub client: &'a str,
+    pub party_uid: &'a str,
+    pub key_uid: &'a str,
+    pub algorithm: i32,
+    /// digest of the signed message
+    pub digest: Option<&'a [u8]>,
+    pub pub_key: &'a [u8],
+    /// outcome of the request, as in the `tofnd_requests_total` metric
+    pub outcome: &'a str,
+}
+
+/// Contents of a record, covered by its hash
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    /// milliseconds since the unix epoch
+    timestamp: u64,
+    rpc: String,
+    client: String,
+    party_uid: String,
+    key_uid: String,
+    algorithm: String,
+    /// hex-encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    /// hex-encoded
+    pub_key: String,
+    outcome: String,
+    /// hex-encoded hash of the previous record
+    prev_hash: String,
+}
+
+/// A line of the audit log
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    entry: Entry,
+    /// hex-encoded SHA-256 hash of the JSON encoding of `entry`
+    hash: String,
+}
+
+impl Entry {
+    fn hash(&self) -> TofndResult<RecordHash> {
+        Ok(Sha256::digest(serde_json::to_vec(self)?).into())
+    }
+}
+
+/// Requests to the writer thread
+enum Message {
+    /// an entry whose `seq` and `prev_hash` are set by the writer, and the sender of the result of
+    /// writing it, if the caller waits for it
+    Record(Box<Entry>, Option<oneshot::Sender<TofndResult<()>>>),
+    /// respond once every previous record is written
+    Flush(oneshot::Sender<()>),
+}
+
+struct Writer {
+    log: File,
+    head_path: PathBuf,
+    /// sequence number of the next record
+    seq: u64,
+    last_hash: RecordHash,
+}
+
+impl Writer {
+    fn run(mut self, mut receiver: mpsc::Receiver<Message>) {
+        while let Some(message) = receiver.blocking_recv() {
+            match message {
+                Message::Record(entry, result_sender) => {
+                    let seq = self.seq;
+                    let result = self.write(*entry);
+                    if let Err(err) = &result {
+                        error!("cannot write audit record {}: {}", seq, err);
+                    }
+                    if let Some(result_sender) = result_sender {
+                        let _ = result_sender.send(result);
+                    }
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, mut entry: Entry) -> TofndResult<()> {
+        entry.seq = self.seq;
+        entry.prev_hash = hex::encode(self.last_hash);
+        let hash = entry.hash()?;
+
+        let mut line = serde_json::to_vec(&Record {
+            entry,
+            hash: hex::encode(hash),
+        })?;
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        // the head must never point past the records on disk
+        self.log.sync_data()?;
+
+        // the record is on disk, so the chain continues from it even if the head isn't updated
+        let seq = self.seq;
+        self.seq += 1;
+        self.last_hash = hash;
+        write_head(&self.head_path, seq, &hash)
+    }
+}
+
+/// Appends records to the audit log; clones share the same writer thread.
+/// A disabled log drops every record.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    sender: Option<mpsc::Sender<Message>>,
+    /// whether [AuditLog::record] waits for the record to be written
+    required: bool,
+}
+
+impl AuditLog {
+    /// Open the audit log in `dir`, continuing the chain of existing records.
+    /// If `required`, [AuditLog::record] fails when the record can't be written.
+    pub fn open(dir: &Path, required: bool) -> TofndResult<Self> {
+        let log_path = dir.join(AUDIT_LOG_FILE_NAME);
+
+        let (seq, last_hash) = match last_record(&log_path)? {
+            Some(record) => (record.entry.seq + 1, decode_hash(&record.hash)?),
+            None => (0, GENESIS_HASH),
+        };
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .map_err(|err| anyhow!("cannot open audit log {:?}: {}", log_path, err))?;
+        let writer = Writer {
+            log,
+            head_path: dir.join(AUDIT_HEAD_FILE_NAME),
+            seq,
+            last_hash,
+        };
+
+        let (sender, receiver) = mpsc::channel(AUDIT_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || writer.run(receiver))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            required,
+        })
+    }
+
+    /// Whether requests must fail when their record can't be written
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Queue a record of `event` for the log. A required log also waits until the record is
+    /// written, and returns the error if it isn't.
+    pub async fn record(&self, event: &AuditEvent<'_>) -> TofndResult<()> {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+
+        let entry = Entry {
+            seq: 0,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis()
+                .try_into()?,
+            rpc: event.rpc.as_str().to_string(),
+            client: event.client.to_string(),
+            party_uid: event.party_uid.to_string(),
+            key_uid: event.key_uid.to_string(),
+            algorithm: Algorithm::try_from(event.algorithm)
+                .map(|algorithm| algorithm.as_str_name())
+                .unwrap_or("INVALID")
+                .to_string(),
+            digest: event.digest.map(hex::encode),
+            pub_key: hex::encode(event.pub_key),
+            outcome: event.outcome.to_string(),
+            prev_hash: String::new(),
+        };
+        if !self.required {
+            return sender
+                .send(Message::Record(Box::new(entry), None))
+                .await
+                .map_err(|_| anyhow!("audit log writer stopped"));
+        }
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        sender
+            .send(Message::Record(Box::new(entry), Some(result_sender)))
+            .await
+            .map_err(|_| anyhow!("audit log writer stopped"))?;
+        result_receiver
+            .await
+            .map_err(|_| anyhow!("audit log writer stopped"))?
+    }
+
+    /// Wait until every queued record is written
+    pub async fn flush(&self) -> TofndResult<()> {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+
+        let (done_sender, done_receiver) = oneshot::channel();
+        sender
+            .send(Message::Flush(done_sender))
+            .await
+            .map_err(|_| anyhow!("audit log writer stopped"))?;
+        done_receiver
+            .await
+            .map_err(|_| anyhow!("audit log writer stopped"))
+    }
+}
+
+/// Check the chain of the audit log in `dir` against its head; returns the number of records
+pub fn verify(dir: &Path) -> TofndResult<u64> {
+    let log_path = dir.join(AUDIT_LOG_FILE_NAME);
+    let head_path = dir.join(AUDIT_HEAD_FILE_NAME);
+
+    let mut hashes = Vec::new();
+    if log_path.exists() {
+        let log = File::open(&log_path)
+            .map_err(|err| anyhow!("cannot open audit log {:?}: {}", log_path, err))?;
+
+        let mut prev_hash = GENESIS_HASH;
+        for (index, line) in BufReader::new(log).lines().enumerate() {
+            let line_number = index + 1;
+            let record: Record = serde_json::from_str(&line?)
+                .map_err(|err| anyhow!("line {}: invalid record: {}", line_number, err))?;
+
+            if record.entry.seq != index as u64 {
+                return Err(anyhow!(
+                    "line {}: expected record {}, found record {}",
+                    line_number,
+                    index,
+                    record.entry.seq
+                ));
+            }
+            if decode_hash(&record.entry.prev_hash)? != prev_hash {
+                return Err(anyhow!(
+                    "line {}: record doesn't follow the previous record",
+                    line_number
+                ));
+            }
+            let hash = record.entry.hash()?;
+            if decode_hash(&record.hash)? != hash {
+                return Err(anyhow!(
+                    "line {}: record doesn't match its hash",
+                    line_number
+                ));
+            }
+
+            prev_hash = hash;
+            hashes.push(hash);
+        }
+    }
+
+    let (head_seq, head_hash) = match read_head(&head_path)? {
+        Some(head) => head,
+        None if hashes.is_empty() => return Ok(0),
+        None => return Err(anyhow!("audit head {:?} is missing", head_path)),
+    };
+    // the head is written after the record, so a crash may leave it one record behind
+    let records = hashes.len() as u64;
+    if head_seq + 2 < records || head_seq >= records {
+        return Err(anyhow!(
+            "audit log has {} records, but its head is record {}; the log was truncated or extended",
+            records,
+            head_seq
+        ));
+    }
+    if hashes[head_seq as usize] != head_hash {
+        return Err(anyhow!("record {} doesn't match the audit head", head_seq));
+    }
+
+    Ok(records)
+}
+
+/// The last record of the log at `path`, if any. A partial last line, left behind by a crash while
+/// it was written, is removed.
+fn last_record(path: &Path) -> TofndResult<Option<Record>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let log = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|err| anyhow!("cannot open audit log {:?}: {}", path, err))?;
+
+    let mut reader = BufReader::new(&log);
+    let mut last_line = None;
+    // length of the log up to the end of its last complete line
+    let mut len = 0;
+    loop {
+        let mut line = Vec::new();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            if read > 0 {
+                warn!(
+                    "removing the partial last record of audit log {:?}: {}",
+                    path,
+                    String::from_utf8_lossy(&line)
+                );
+                log.set_len(len)?;
+                log.sync_data()?;
+            }
+            break;
+        }
+        len += read as u64;
+        last_line = Some(String::from_utf8(line)?);
+    }
+
+    last_line
+        .map(|line| {
+            serde_json::from_str(&line).map_err(|err| {
+                anyhow!(
+                    "invalid last record of audit log {:?}, run `tofnd audit verify`: {}",
+                    path,
+                    err
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Replace the head file atomically
+fn write_head(path: &Path, seq: u64, hash: &RecordHash) -> TofndResult<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(format!("{} {}\n", seq, hex::encode(hash)).as_bytes())?;
+    // a crash after the rename must not leave an empty head behind
+    tmp.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn read_head(path: &Path) -> TofndResult<Option<(u64, RecordHash)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let head = std::fs::read_to_string(path)?;
+
+    let (seq, hash) = head
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid audit head {:?}", path))?;
+    Ok(Some((seq.parse()?, decode_hash(hash)?)))
+}
+
+fn decode_hash(hash: &str) -> TofndResult<RecordHash> {
+    hex::decode(hash)?
+        .try_into()
+        .map_err(|_| anyhow!("invalid record hash {:?}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    fn sign_event(key_uid: &str) -> AuditEvent<'_> {
+        AuditEvent {
+            rpc: Rpc::Sign,
+            client: "vald",
+            party_uid: "party",
+            key_uid,
+            algorithm: Algorithm::Ecdsa as i32,
+            digest: Some(&[42; 32][..]),
+            pub_key: &[2; 33],
+            outcome: "signature",
+        }
+    }
+
+    async fn write_records(dir: &Path, count: usize) {
+        let audit_log = AuditLog::open(dir, false).unwrap();
+        for index in 0..count {
+            audit_log
+                .record(&sign_event(&format!("key-{}", index)))
+                .await
+                .unwrap();
+        }
+        audit_log.flush().await.unwrap();
+    }
+
+    fn lines(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join(AUDIT_LOG_FILE_NAME))
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn write_lines(dir: &Path, lines: &[String]) {
+        std::fs::write(dir.join(AUDIT_LOG_FILE_NAME), lines.join("\n") + "\n").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let dir = testdir!();
+        assert_eq!(verify(&dir).unwrap(), 0);
+
+        write_records(&dir, 3).await;
+        assert_eq!(verify(&dir).unwrap(), 3);
+
+        // reopening the log continues the chain
+        write_records(&dir, 2).await;
+        assert_eq!(verify(&dir).unwrap(), 5);
+
+        // a disabled log writes nothing
+        AuditLog::default()
+            .record(&sign_event("key"))
+            .await
+            .unwrap();
+        assert_eq!(verify(&dir).unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_required() {
+        let dir = testdir!();
+        let audit_log = AuditLog::open(&dir, true).unwrap();
+        audit_log.record(&sign_event("key-0")).await.unwrap();
+        // a required record is written once it is recorded
+        assert_eq!(verify(&dir).unwrap(), 1);
+
+        // the head can't be replaced by a directory
+        let head_path = dir.join(AUDIT_HEAD_FILE_NAME);
+        std::fs::remove_file(&head_path).unwrap();
+        std::fs::create_dir(&head_path).unwrap();
+        assert!(audit_log.record(&sign_event("key-1")).await.is_err());
+
+        // without a required log, the failure is only logged
+        let audit_log = AuditLog::open(&dir, false).unwrap();
+        assert!(audit_log.record(&sign_event("key-2")).await.is_ok());
+        audit_log.flush().await.unwrap();
+
+        // records whose head wasn't written still continue the chain
+        std::fs::remove_dir(&head_path).unwrap();
+        write_records(&dir, 1).await;
+        assert_eq!(verify(&dir).unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_partial_last_record() {
+        let dir = testdir!();
+        write_records(&dir, 2).await;
+
+        // a crash while a record is written leaves part of its line behind
+        let mut partial = lines(&dir);
+        partial.push(partial[1][..40].to_string());
+        std::fs::write(dir.join(AUDIT_LOG_FILE_NAME), partial.join("\n")).unwrap();
+
+        // reopening the log removes the partial record and continues the chain
+        write_records(&dir, 1).await;
+        assert_eq!(verify(&dir).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_verify_detects_tampering() {
+        let dir = testdir!();
+        write_records(&dir, 4).await;
+        let original = lines(&dir);
+
+        // edited record
+        let mut edited = original.clone();
+        edited[1] = edited[1].replace("key-1", "key-9");
+        write_lines(&dir, &edited);
+        assert!(verify(&dir).is_err());
+
+        // removed record
+        let mut removed = original.clone();
+        removed.remove(2);
+        write_lines(&dir, &removed);
+        assert!(verify(&dir).is_err());
+
+        // truncated log
+        write_lines(&dir, &original[..2]);
+        assert!(verify(&dir).is_err());
+
+        // removed log
+        std::fs::remove_file(dir.join(AUDIT_LOG_FILE_NAME)).unwrap();
+        assert!(verify(&dir).is_err());
+
+        write_lines(&dir, &original);
+        assert_eq!(verify(&dir).unwrap(), 4);
+    }
+}
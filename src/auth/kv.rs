@@ -0,0 +1,125 @@
+//! Storage of [AuthToken]s in the kv store and handling of [TokenCmd]s.
+
+use rand::RngCore;
+use tofn::sdk::api::{deserialize, serialize};
+
+use super::{AuthToken, TokenCmd};
+use crate::{kv_manager::KvManager, TofndResult};
+use anyhow::anyhow;
+
+// logging
+use tracing::info;
+
+/// key to store auth tokens
+const AUTH_TOKENS_KEY: &str = "auth_tokens";
+
+/// number of random bytes of a new token
+const TOKEN_LEN: usize = 32;
+
+/// implement token-specific functions for KvManager
+impl KvManager {
+    /// Get all tokens stored in the kv store
+    pub async fn auth_tokens(&self) -> TofndResult<Vec<AuthToken>> {
+        if !self.kv().exists(AUTH_TOKENS_KEY).await? {
+            return Ok(vec![]);
+        }
+
+        let encoded_tokens = self.kv().get(AUTH_TOKENS_KEY).await?;
+        deserialize(&encoded_tokens).ok_or_else(|| anyhow!("cannot deserialize auth tokens"))
+    }
+
+    /// Replace all tokens stored in the kv store with `tokens`
+    async fn put_auth_tokens(&self, tokens: &[AuthToken]) -> TofndResult<()> {
+        let encoded_tokens =
+            serialize(&tokens).map_err(|_| anyhow!("cannot serialize auth tokens"))?;
+
+        // a single write, so that a crash never loses the existing tokens
+        self.kv().replace(AUTH_TOKENS_KEY, encoded_tokens).await?;
+
+        Ok(())
+    }
+
+    /// Add, list or revoke tokens
+    pub async fn handle_token_cmd(&self, cmd: &TokenCmd) -> TofndResult<()> {
+        let mut tokens = self.auth_tokens().await?;
+
+        match cmd {
+            TokenCmd::Add(label) => {
+                if tokens.iter().any(|token| &token.label == label) {
+                    return Err(anyhow!("a token with label <{}> already exists", label));
+                }
+
+                let mut token = [0u8; TOKEN_LEN];
+                rand::thread_rng().fill_bytes(&mut token);
+                let token = hex::encode(token);
+
+                tokens.push(AuthToken::new(label.clone(), &token));
+                self.put_auth_tokens(&tokens).await?;
+
+                info!("Added token with label <{}>", label);
+                // the token is only shown once; tofnd keeps its hash
+                println!("{}", token);
+            }
+            TokenCmd::List => {
+                for token in tokens {
+                    println!("{}", token.label);
+                }
+            }
+            TokenCmd::Revoke(label) => {
+                let count = tokens.len();
+                tokens.retain(|token| &token.label != label);
+                if tokens.len() == count {
+                    return Err(anyhow!("no token with label <{}> exists", label));
+                }
+
+                self.put_auth_tokens(&tokens).await?;
+                info!("Revoked token with label <{}>", label);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypted_sled::get_test_password;
+    use testdir::testdir;
+    use tracing_test::traced_test;
+
+    fn labels(tokens: &[AuthToken]) -> Vec<&str> {
+        tokens.iter().map(|token| token.label.as_str()).collect()
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_token_cmds() {
+        let kv = KvManager::new(testdir!(), get_test_password()).unwrap();
+        assert!(kv.auth_tokens().await.unwrap().is_empty());
+
+        for label in ["vald", "admin"] {
+            kv.handle_token_cmd(&TokenCmd::Add(label.to_string()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(labels(&kv.auth_tokens().await.unwrap()), ["vald", "admin"]);
+
+        // labels are unique
+        assert!(kv
+            .handle_token_cmd(&TokenCmd::Add("vald".to_string()))
+            .await
+            .is_err());
+
+        kv.handle_token_cmd(&TokenCmd::Revoke("vald".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(labels(&kv.auth_tokens().await.unwrap()), ["admin"]);
+
+        // can't revoke a missing token
+        assert!(kv
+            .handle_token_cmd(&TokenCmd::Revoke("vald".to_string()))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Bearer-token authentication of gRPC clients.
+//!
+//! Clients authenticate with an `authorization: Bearer <token>` metadata header.
+//! Every token is bound to a label that identifies the client in the logs.
+//! Only SHA-256 hashes of tokens are kept, both in the kv store and in memory.
+
+mod kv;
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tonic::{service::Interceptor, Request, Status};
+
+// error handling
+use crate::TofndResult;
+use anyhow::anyhow;
+
+// logging
+use tracing::warn;
+
+/// metadata header that carries the bearer token
+const AUTHORIZATION_HEADER: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+type TokenHash = [u8; 32];
+
+fn hash_token(token: &str) -> TokenHash {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Token commands, executed instead of starting the gRPC daemon
+#[derive(Clone, Debug)]
+pub enum TokenCmd {
+    /// create a new token bound to a label
+    Add(String),
+    /// list the labels of all tokens
+    List,
+    /// revoke the token bound to a label
+    Revoke(String),
+}
+
+/// A token bound to a label. Only the hash of the token is stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthToken {
+    pub label: String,
+    token_hash: TokenHash,
+}
+
+impl AuthToken {
+    pub fn new(label: String, token: &str) -> Self {
+        Self {
+            label,
+            token_hash: hash_token(token),
+        }
+    }
+}
+
+/// Label of an authenticated client; added to the request extensions by [Authenticator]
+#[derive(Clone, Debug)]
+pub struct ClientLabel(pub String);
+
+/// Returns the label of the client that sent `request`, if it was authenticated
+pub fn client_label<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<ClientLabel>()
+        .map(|label| label.0.clone())
+}
+
+/// gRPC interceptor that rejects requests without a valid bearer token with `Unauthenticated`.
+/// A disabled [Authenticator] lets all requests through.
+#[derive(Clone)]
+pub struct Authenticator {
+    /// token hash -> label; [None] if authentication is disabled
+    tokens: Option<Arc<HashMap<TokenHash, String>>>,
+}
+
+impl Authenticator {
+    /// Accept all requests
+    pub fn disabled() -> Self {
+        Self { tokens: None }
+    }
+
+    /// Accept only requests that carry one of `tokens`
+    pub fn new(tokens: Vec<AuthToken>) -> TofndResult<Self> {
+        if tokens.is_empty() {
+            return Err(anyhow!(
+                "authentication is enabled but no tokens exist. Use `tofnd token add <label>` to create one"
+            ));
+        }
+
+        let tokens = tokens
+            .into_iter()
+            .map(|token| (token.token_hash, token.label))
+            .collect();
+        Ok(Self {
+            tokens: Some(Arc::new(tokens)),
+        })
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let tokens = match &self.tokens {
+            Some(tokens) => tokens,
+            None => return Ok(request),
+        };
+
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+            .ok_or_else(|| {
+                warn!("rejected request without bearer token");
+                Status::unauthenticated("missing bearer token")
+            })?;
+
+        let label = match tokens.get(&hash_token(token)) {
+            Some(label) => ClientLabel(label.clone()),
+            None => {
+                warn!("rejected request with invalid bearer token");
+                return Err(Status::unauthenticated("invalid bearer token"));
+            }
+        };
+
+        request.extensions_mut().insert(label);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn request_with_token(token: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            request.metadata_mut().insert(
+                AUTHORIZATION_HEADER,
+                format!("{}{}", BEARER_PREFIX, token).parse().unwrap(),
+            );
+        }
+        request
+    }
+
+    #[test]
+    fn test_authenticator() {
+        let mut authenticator =
+            Authenticator::new(vec![AuthToken::new("vald".to_string(), "secret")]).unwrap();
+
+        let request = authenticator
+            .call(request_with_token(Some("secret")))
+            .unwrap();
+        assert_eq!(client_label(&request), Some("vald".to_string()));
+
+        for token in [None, Some("wrong secret")] {
+            let status = authenticator.call(request_with_token(token)).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn test_authenticator_disabled() {
+        let mut authenticator = Authenticator::disabled();
+
+        let request = authenticator.call(request_with_token(None)).unwrap();
+        assert_eq!(client_label(&request), None);
+
+        assert!(Authenticator::new(vec![]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! The `tofnd.toml` configuration file.
+//! Keys are named after the command line flags they stand in for, e.g. `tls-cert = "server.crt"`.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{is_valid_rate, AdminListener, Config};
+
+// error handling
+use crate::TofndResult;
+use anyhow::anyhow;
+
+/// name of the configuration file inside the tofnd directory
+const CONFIG_FILE_NAME: &str = "tofnd.toml";
+
+/// placeholder for secrets in printed configurations
+const REDACTED: &str = "<redacted>";
+
+/// Contents of a configuration file; every key is optional
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(super) struct FileConfig {
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub socket: Option<PathBuf>,
+    pub socket_mode: Option<String>,
+    pub socket_uid: Option<u32>,
+    pub socket_gid: Option<u32>,
+    pub metrics_port: Option<u16>,
+    pub metrics_address: Option<String>,
+    pub shutdown_grace_period: Option<u64>,
+    pub auth: Option<bool>,
+    /// `label:token` pairs
+    pub auth_tokens: Option<Vec<String>>,
+    pub max_concurrent_requests: Option<usize>,
+    pub rate_limit: Option<f64>,
+    pub rate_limit_burst: Option<u32>,
+    pub crypto_threads: Option<usize>,
+    pub policy: Option<PathBuf>,
+    pub audit_required: Option<bool>,
+    pub admin_port: Option<u16>,
+    pub admin_socket: Option<PathBuf>,
+}
+
+impl FileConfig {
+    /// Read the configuration file at `path`, or at `tofnd_path`/tofnd.toml if no path is given.
+    /// A missing file is only an error if `path` was given explicitly.
+    pub fn load(path: Option<&Path>, tofnd_path: &Path) -> TofndResult<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let default_path = tofnd_path.join(CONFIG_FILE_NAME);
+                if !default_path.exists() {
+                    return Ok(Self::default());
+                }
+                default_path
+            }
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| anyhow!("cannot read config file {:?}: {}", path, err))?;
+        let file: Self = toml::from_str(&contents)
+            .map_err(|err| anyhow!("invalid config file {:?}: {}", path, err))?;
+        file.validate()
+            .map_err(|err| anyhow!("invalid config file {:?}: {}", path, err))?;
+
+        Ok(file)
+    }
+
+    // mirror the checks of the command line value parsers that don't have a shared helper
+    fn validate(&self) -> TofndResult<()> {
+        if self.max_concurrent_requests == Some(0) {
+            return Err(anyhow!("max-concurrent-requests must be positive"));
+        }
+        if self.rate_limit.is_some_and(|rate| !is_valid_rate(rate)) {
+            return Err(anyhow!("rate-limit must be a positive number"));
+        }
+        if self.rate_limit_burst == Some(0) {
+            return Err(anyhow!("rate-limit-burst must be positive"));
+        }
+        if self.crypto_threads == Some(0) {
+            return Err(anyhow!("crypto-threads must be positive"));
+        }
+        // the output of `--print-config` must not turn the placeholder into a valid token
+        if self
+            .auth_tokens
+            .iter()
+            .flatten()
+            .any(|label_and_token| label_and_token.ends_with(&format!(":{}", REDACTED)))
+        {
+            return Err(anyhow!(
+                "auth-tokens holds a redacted token; replace it with the token itself"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The effective configuration `cfg` with secrets redacted
+    pub fn redacted(cfg: &Config) -> Self {
+        Self {
+            address: Some(cfg.ip.clone()),
+            port: Some(cfg.port),
+            tls_cert: cfg.tls.as_ref().map(|tls| tls.cert.clone()),
+            tls_key: cfg.tls.as_ref().map(|tls| tls.key.clone()),
+            tls_client_ca: cfg.tls.as_ref().and_then(|tls| tls.client_ca.clone()),
+            socket: cfg.unix_socket.as_ref().map(|socket| socket.path.clone()),
+            socket_mode: cfg
+                .unix_socket
+                .as_ref()
+                .map(|socket| format!("{:o}", socket.mode)),
+            socket_uid: cfg.unix_socket.as_ref().and_then(|socket| socket.uid),
+            socket_gid: cfg.unix_socket.as_ref().and_then(|socket| socket.gid),
+            metrics_port: cfg.metrics_addr.map(|addr| addr.port()),
+            metrics_address: cfg.metrics_addr.map(|addr| addr.ip().to_string()),
+            shutdown_grace_period: Some(cfg.shutdown_grace_period.as_secs()),
+            auth: Some(cfg.auth),
+            auth_tokens: Some(
+                cfg.auth_tokens
+                    .iter()
+                    .map(|token| format!("{}:{}", token.label, REDACTED))
+                    .collect(),
+            ),
+            max_concurrent_requests: cfg.limits.max_concurrent_requests,
+            rate_limit: cfg
+                .limits
+                .rate_limit
+                .map(|rate_limit| rate_limit.per_second),
+            rate_limit_burst: cfg.limits.rate_limit.map(|rate_limit| rate_limit.burst),
+            crypto_threads: cfg.limits.crypto_threads,
+            policy: cfg.policy.clone(),
+            audit_required: Some(cfg.audit_required),
+            admin_port: match &cfg.admin {
+                Some(AdminListener::Tcp(addr)) => Some(addr.port()),
+                _ => None,
+            },
+            admin_socket: match &cfg.admin {
+                Some(AdminListener::Unix(socket)) => Some(socket.path.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[test]
+    fn test_load() {
+        let dir = testdir!();
+
+        // a missing default file is an empty configuration
+        assert_eq!(FileConfig::load(None, &dir).unwrap(), FileConfig::default());
+
+        std::fs::write(
+            dir.join(CONFIG_FILE_NAME),
+            "port = 50052\nauth = true\nauth-tokens = [\"vald:secret\"]\n",
+        )
+        .unwrap();
+        let file = FileConfig::load(None, &dir).unwrap();
+        assert_eq!(file.port, Some(50052));
+        assert_eq!(file.auth, Some(true));
+        assert_eq!(file.auth_tokens, Some(vec!["vald:secret".to_string()]));
+
+        // an explicit path must exist, and unknown keys are rejected
+        assert!(FileConfig::load(Some(&dir.join("missing.toml")), &dir).is_err());
+        std::fs::write(dir.join("typo.toml"), "prot = 50052\n").unwrap();
+        assert!(FileConfig::load(Some(&dir.join("typo.toml")), &dir).is_err());
+    }
+}
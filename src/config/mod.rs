@@ -1,18 +1,57 @@
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use clap::{builder::PossibleValuesParser, crate_version, value_parser, Arg, ArgAction, Command};
+use clap::{
+    builder::{PossibleValuesParser, RangedU64ValueParser},
+    crate_version,
+    parser::ValueSource,
+    value_parser, Arg, ArgAction, ArgMatches, Command,
+};
+use tokio::net::UnixListener;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
 
 // error handling
-use crate::{encrypted_sled::PasswordMethod, mnemonic::Cmd, TofndResult};
+use crate::{
+    addr,
+    auth::{AuthToken, TokenCmd},
+    encrypted_sled::PasswordMethod,
+    mnemonic::{Cmd, ImportFile, Language, PhraseFormat, ShareSplit},
+    multisig::limiter::{Limits, RateLimit},
+    TofndResult,
+};
 use anyhow::anyhow;
 
+// logging
+use tracing::warn;
+
+mod file;
+use file::FileConfig;
+
 // TODO: move these into constants.rs
 const DEFAULT_PATH_ROOT: &str = ".tofnd";
 const TOFND_HOME_ENV_VAR: &str = "TOFND_HOME";
 const DEFAULT_MNEMONIC_CMD: &str = "existing";
 const DEFAULT_IP: &str = "127.0.0.1";
 const DEFAULT_PORT: &str = "50051";
-const AVAILABLE_MNEMONIC_CMDS: &[&str] = &["existing", "create", "import", "export", "rotate"];
+const DEFAULT_SOCKET_MODE: &str = "600";
+const DEFAULT_METRICS_IP: &str = "127.0.0.1";
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: &str = "30";
+const DEFAULT_RATE_LIMIT_BURST: &str = "10";
+const AUTH_TOKENS_ENV_VAR: &str = "TOFND_AUTH_TOKENS";
+const AVAILABLE_MNEMONIC_CMDS: &[&str] = &[
+    "existing",
+    "create",
+    "import",
+    "export",
+    "rotate",
+    "export-shares",
+    "import-shares",
+    "verify",
+];
 
 // default path is ~/.tofnd
 fn default_tofnd_dir() -> TofndResult<String> {
@@ -30,26 +69,203 @@ pub struct Config {
     pub ip: String,
     pub port: u16,
     pub mnemonic_cmd: Cmd,
+    /// if set, write mnemonic exports in plaintext instead of prompting for an export passphrase
+    pub plaintext_export: bool,
     pub tofnd_path: PathBuf,
     pub password_method: PasswordMethod,
+    pub tls: Option<TlsConfig>,
+    /// if set, serve over a unix domain socket instead of `ip`:`port`
+    pub unix_socket: Option<UnixSocketConfig>,
+    /// if set, serve Prometheus metrics at this address
+    pub metrics_addr: Option<SocketAddr>,
+    /// time given to in-flight requests to complete after a shutdown signal
+    pub shutdown_grace_period: Duration,
+    /// if set, gRPC clients must authenticate with a bearer token
+    pub auth: bool,
+    /// tokens given in the config, in addition to the ones in the kv store
+    pub auth_tokens: Vec<AuthToken>,
+    /// if set, execute a token command instead of starting the gRPC daemon
+    pub token_cmd: Option<TokenCmd>,
+    /// if set, index the keys of the key uids listed in this file instead of starting the gRPC daemon
+    pub index_rebuild: Option<PathBuf>,
+    /// if set, verify the audit log instead of starting the gRPC daemon
+    pub audit_verify: bool,
+    /// if set, requests fail when their audit record can't be written
+    pub audit_required: bool,
+    /// concurrency and rate limits of multisig requests
+    pub limits: Limits,
+    /// if set, check keygen and sign requests against the signing policy in this file
+    pub policy: Option<PathBuf>,
+    /// if set, serve the admin service on this local address or socket
+    pub admin: Option<AdminListener>,
+    /// if set, print the effective configuration and exit
+    pub print_config: bool,
+}
+
+impl Config {
+    /// The effective configuration in the format of the config file, with secrets redacted
+    pub fn to_redacted_toml(&self) -> TofndResult<String> {
+        Ok(format!(
+            "# directory = {:?}\n{}",
+            self.tofnd_path,
+            toml::to_string(&FileConfig::redacted(self))?
+        ))
+    }
+}
+
+/// Paths to the PEM-encoded files used to serve gRPC over TLS
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// if set, only clients presenting a certificate signed by one of these CAs are accepted (mutual TLS)
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Read the certificate, private key and client CA bundle from disk
+    pub fn server_tls_config(&self) -> TofndResult<ServerTlsConfig> {
+        let cert = read_pem(&self.cert)?;
+        let key = read_pem(&self.key)?;
+        let tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        Ok(match &self.client_ca {
+            Some(client_ca) => {
+                tls_config.client_ca_root(Certificate::from_pem(read_pem(client_ca)?))
+            }
+            None => tls_config,
+        })
+    }
+}
+
+/// Local address the admin service listens on
+#[derive(Clone, Debug)]
+pub enum AdminListener {
+    Tcp(SocketAddr),
+    Unix(UnixSocketConfig),
+}
+
+impl AdminListener {
+    /// The admin service only listens on the loopback interface
+    fn tcp(port: u16) -> TofndResult<Self> {
+        Ok(Self::Tcp(addr(DEFAULT_IP, port)?))
+    }
+
+    /// The admin socket is only accessible to the owner of the tofnd process
+    fn unix(path: PathBuf) -> Self {
+        Self::Unix(UnixSocketConfig {
+            path,
+            mode: 0o600,
+            uid: None,
+            gid: None,
+        })
+    }
+}
+
+/// Path, permissions and ownership of the unix domain socket the gRPC server listens on
+#[derive(Clone, Debug)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl UnixSocketConfig {
+    /// Bind a listener at `path` and apply the configured permissions and owner; the socket file
+    /// is removed once the returned [SocketFile] is dropped.
+    /// A stale socket left behind by a previous run is removed; any other existing file is an error.
+    pub fn bind(&self) -> TofndResult<(UnixListener, SocketFile)> {
+        if let Ok(metadata) = std::fs::symlink_metadata(&self.path) {
+            if !metadata.file_type().is_socket() {
+                return Err(anyhow!(
+                    "cannot bind unix socket: {:?} exists and is not a socket",
+                    self.path
+                ));
+            }
+            std::fs::remove_file(&self.path)?;
+        }
+
+        let listener = UnixListener::bind(&self.path)?;
+        let socket_file = SocketFile(self.path.clone());
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(self.mode))?;
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::chown(&self.path, self.uid, self.gid)?;
+        }
+
+        Ok((listener, socket_file))
+    }
+}
+
+/// Removes a unix socket file when dropped, so that no dangling socket is left behind on any exit path
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            warn!("cannot remove unix socket {:?}: {}", self.0, err);
+        }
+    }
+}
+
+// parse a `label:token` pair
+fn parse_auth_token(label_and_token: &str) -> Result<AuthToken, String> {
+    match label_and_token.split_once(':') {
+        Some((label, token)) if !label.is_empty() && !token.is_empty() => {
+            Ok(AuthToken::new(label.to_string(), token))
+        }
+        _ => Err("expected <label>:<token>".to_string()),
+    }
+}
+
+// parse a positive number of requests per second
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    rate.parse::<f64>()
+        .ok()
+        .filter(|rate| is_valid_rate(*rate))
+        .ok_or_else(|| format!("invalid rate <{}>, expected a positive number", rate))
+}
+
+fn is_valid_rate(rate: f64) -> bool {
+    rate.is_finite() && rate > 0.0
+}
+
+// parse an octal file mode such as `600` or `0660`
+fn parse_socket_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("invalid octal file mode <{}>", mode))
+}
+
+fn read_pem(path: &Path) -> TofndResult<Vec<u8>> {
+    std::fs::read(path).map_err(|err| anyhow!("cannot read PEM file {:?}: {}", path, err))
 }
 
 pub fn parse_args() -> TofndResult<Config> {
-    let app = Command::new("tofnd")
+    config_from_matches(command()?.get_matches())
+}
+
+fn command() -> TofndResult<Command> {
+    Ok(Command::new("tofnd")
         .about("A cryptographic signing service")
         .version(crate_version!())
         .arg(
             Arg::new("ip")
                 .long("address")
+                .env("TOFND_ADDRESS")
                 .short('a')
                 .required(false)
+                .conflicts_with("socket")
                 .default_value(DEFAULT_IP),
         )
         .arg(
             Arg::new("port")
                 .long("port")
+                .env("TOFND_PORT")
                 .short('p')
                 .required(false)
+                .conflicts_with("socket")
                 .value_parser(value_parser!(u16))
                 .default_value(DEFAULT_PORT),
         )
@@ -71,6 +287,60 @@ pub fn parse_args() -> TofndResult<Config> {
                 .default_value(DEFAULT_MNEMONIC_CMD)
                 .value_parser(PossibleValuesParser::new(AVAILABLE_MNEMONIC_CMDS))
         )
+        .arg(
+            Arg::new("import-file")
+                .help("Read the mnemonic of `-m import` or `-m verify` from this file instead of standard input. Repeat to read the shares of `-m import-shares` from files.")
+                .long("import-file")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("delete-import-file")
+                .help("Overwrite and delete --import-file after a successful import.")
+                .long("delete-import-file")
+                .required(false)
+                .requires("import-file")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("share-threshold")
+                .help("Number of shares of `-m export-shares` needed to recover the mnemonic.")
+                .long("share-threshold")
+                .required(false)
+                .default_value("2")
+                .value_parser(value_parser!(u8)),
+        )
+        .arg(
+            Arg::new("share-count")
+                .help("Number of shares `-m export-shares` splits the mnemonic into.")
+                .long("share-count")
+                .required(false)
+                .default_value("3")
+                .value_parser(value_parser!(u8)),
+        )
+        .arg(
+            Arg::new("word-count")
+                .help("Number of words of the mnemonic of `-m create`.")
+                .long("word-count")
+                .required(false)
+                .default_value("24")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("language")
+                .help("Wordlist language of the mnemonic of `-m create`, `-m import`, `-m import-shares` and `-m verify` [default: en]. Seeds are derived from the phrase in this language. `-m export` only exports in the language the mnemonic was stored with.")
+                .long("language")
+                .required(false)
+                .value_parser(PossibleValuesParser::new(Language::CODES)),
+        )
+        .arg(
+            Arg::new("plaintext-export")
+                .help("Write the mnemonic export file in plaintext instead of encrypting it under an export passphrase.")
+                .long("plaintext-export")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("directory")
                 .long("directory")
@@ -78,37 +348,646 @@ pub fn parse_args() -> TofndResult<Config> {
                 .required(false)
                 .env(TOFND_HOME_ENV_VAR)
                 .default_value(default_tofnd_dir()?),
-        );
+        )
+        .arg(
+            Arg::new("config")
+                .help("Path of the TOML config file. (default: <directory>/tofnd.toml, if it exists)")
+                .long("config")
+                .short('c')
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("print-config")
+                .help("Print the effective configuration, with secrets redacted, and exit.")
+                .long("print-config")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tls-cert")
+                .help("PEM-encoded certificate chain of the gRPC server. Enables TLS.")
+                .long("tls-cert")
+                .env("TOFND_TLS_CERT")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("tls-key")
+                .help("PEM-encoded private key of the gRPC server.")
+                .long("tls-key")
+                .env("TOFND_TLS_KEY")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("tls-client-ca")
+                .help("PEM-encoded CA bundle used to verify client certificates. Enables mutual TLS.")
+                .long("tls-client-ca")
+                .env("TOFND_TLS_CLIENT_CA")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("socket")
+                .help("Serve over a unix domain socket at this path instead of --address/--port.")
+                .long("socket")
+                .env("TOFND_SOCKET")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("socket-mode")
+                .help("Octal file permissions of the unix domain socket.")
+                .long("socket-mode")
+                .env("TOFND_SOCKET_MODE")
+                .required(false)
+                .value_parser(parse_socket_mode)
+                .default_value(DEFAULT_SOCKET_MODE),
+        )
+        .arg(
+            Arg::new("socket-uid")
+                .help("Numeric user id that owns the unix domain socket.")
+                .long("socket-uid")
+                .env("TOFND_SOCKET_UID")
+                .required(false)
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("socket-gid")
+                .help("Numeric group id that owns the unix domain socket.")
+                .long("socket-gid")
+                .env("TOFND_SOCKET_GID")
+                .required(false)
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("metrics-port")
+                .help("Serve Prometheus metrics over HTTP on this port. (default: disabled)")
+                .long("metrics-port")
+                .env("TOFND_METRICS_PORT")
+                .required(false)
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("metrics-address")
+                .help("Address to serve Prometheus metrics on.")
+                .long("metrics-address")
+                .env("TOFND_METRICS_ADDRESS")
+                .required(false)
+                .default_value(DEFAULT_METRICS_IP),
+        )
+        .arg(
+            Arg::new("shutdown-grace-period")
+                .help("Seconds given to in-flight requests to complete after SIGTERM or SIGINT.")
+                .long("shutdown-grace-period")
+                .env("TOFND_SHUTDOWN_GRACE_PERIOD")
+                .required(false)
+                .value_parser(value_parser!(u64))
+                .default_value(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS),
+        )
+        .arg(
+            Arg::new("auth")
+                .help("Require gRPC clients to send a valid `authorization: Bearer <token>` header.")
+                .long("auth")
+                .env("TOFND_AUTH")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("auth-token")
+                .help("Accept <token> and log its client as <label>, in addition to the tokens in the kv store.")
+                .long("auth-token")
+                .value_name("label:token")
+                .required(false)
+                .env(AUTH_TOKENS_ENV_VAR)
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(parse_auth_token),
+        )
+        .arg(
+            Arg::new("max-concurrent-requests")
+                .help("Reject multisig requests with RESOURCE_EXHAUSTED while this many are in progress. (default: unlimited)")
+                .long("max-concurrent-requests")
+                .env("TOFND_MAX_CONCURRENT_REQUESTS")
+                .required(false)
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("rate-limit")
+                .help("Reject multisig requests with RESOURCE_EXHAUSTED once an authenticated client, or all unauthenticated clients together, exceed this many requests per second. (default: unlimited)")
+                .long("rate-limit")
+                .env("TOFND_RATE_LIMIT")
+                .required(false)
+                .value_parser(parse_rate),
+        )
+        .arg(
+            Arg::new("rate-limit-burst")
+                .help("Number of requests a client can send at once before --rate-limit applies.")
+                .long("rate-limit-burst")
+                .env("TOFND_RATE_LIMIT_BURST")
+                .required(false)
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value(DEFAULT_RATE_LIMIT_BURST),
+        )
+        .arg(
+            Arg::new("crypto-threads")
+                .help("Number of threads that derive keys and sign. (default: one per CPU)")
+                .long("crypto-threads")
+                .env("TOFND_CRYPTO_THREADS")
+                .required(false)
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("policy")
+                .help("Check keygen and sign requests against the signing policy in this TOML file. Reloaded on SIGHUP. (default: allow all)")
+                .long("policy")
+                .env("TOFND_POLICY")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("audit-required")
+                .help("Fail multisig requests whose audit record can't be written, and only return signatures once they are recorded. (default: failures are only logged)")
+                .long("audit-required")
+                .env("TOFND_AUDIT_REQUIRED")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("admin-port")
+                .help("Serve the admin service on this port of 127.0.0.1. Requires --auth. (default: disabled)")
+                .long("admin-port")
+                .env("TOFND_ADMIN_PORT")
+                .required(false)
+                .conflicts_with("admin-socket")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("admin-socket")
+                .help("Serve the admin service on a unix domain socket at this path. (default: disabled)")
+                .long("admin-socket")
+                .env("TOFND_ADMIN_SOCKET")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .subcommand(
+            Command::new("token")
+                .about("Manage the bearer tokens of gRPC clients and exit")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Create a new token bound to <label> and print it")
+                        .arg(Arg::new("label").required(true)),
+                )
+                .subcommand(Command::new("list").about("List the labels of all tokens"))
+                .subcommand(
+                    Command::new("revoke")
+                        .about("Revoke the token bound to <label>")
+                        .arg(Arg::new("label").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Manage the index of the mnemonic of each key and exit")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("rebuild")
+                        .about("Index the keys of the key uids listed in <key-uids>, one per line")
+                        .arg(
+                            Arg::new("key-uids")
+                                .required(true)
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Manage the audit log of multisig requests and exit")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("verify")
+                        .about("Check that the audit log was not modified or truncated"),
+                ),
+        ))
+}
 
-    let matches = app.get_matches();
+fn config_from_matches(matches: ArgMatches) -> TofndResult<Config> {
+    let tofnd_path: PathBuf = matches
+        .get_one::<String>("directory")
+        .ok_or_else(|| anyhow!("directory value"))?
+        .into();
+    let file = FileConfig::load(
+        matches.get_one::<PathBuf>("config").map(PathBuf::as_path),
+        &tofnd_path,
+    )?;
 
-    let ip = matches
-        .get_one::<String>("ip")
-        .ok_or_else(|| anyhow!("ip value"))?
-        .clone();
-    let port = *matches
-        .get_one::<u16>("port")
-        .ok_or_else(|| anyhow!("port value"))?;
+    // a socket replaces the address and port, wherever either is set
+    let address_set = ["ip", "port"].iter().any(|id| is_explicit(&matches, id))
+        || file.address.is_some()
+        || file.port.is_some();
+    let ip = layered(&matches, "ip", file.address).ok_or_else(|| anyhow!("ip value"))?;
+    let port = layered(&matches, "port", file.port).ok_or_else(|| anyhow!("port value"))?;
     let mnemonic_cmd = Cmd::from_string(
         matches
             .get_one::<String>("mnemonic")
             .ok_or_else(|| anyhow!("cmd value"))?,
     )?;
-    let tofnd_path = matches
-        .get_one::<String>("directory")
-        .ok_or_else(|| anyhow!("directory value"))?
-        .into();
+    let import_files: Vec<_> = matches
+        .get_many::<PathBuf>("import-file")
+        .unwrap_or_default()
+        .map(|path| ImportFile {
+            path: path.clone(),
+            delete: matches.get_flag("delete-import-file"),
+        })
+        .collect();
+    let language = matches
+        .get_one::<String>("language")
+        .map(|code| Language::from_code(code).ok_or_else(|| anyhow!("language value")))
+        .transpose()?;
+    let mnemonic_cmd = match (mnemonic_cmd, import_files.as_slice()) {
+        (Cmd::Import(..) | Cmd::Verify(..), [_, _, ..]) => {
+            return Err(anyhow!(
+                "`--mnemonic import` and `--mnemonic verify` take a single import-file"
+            ))
+        }
+        (Cmd::Import(..), files) => {
+            Cmd::Import(files.first().cloned(), language.unwrap_or_default())
+        }
+        (Cmd::Verify(..), _) if matches.get_flag("delete-import-file") => {
+            return Err(anyhow!(
+                "`--mnemonic verify` doesn't import the mnemonic, so it can't delete-import-file"
+            ))
+        }
+        (Cmd::Verify(..), files) => {
+            Cmd::Verify(files.first().cloned(), language.unwrap_or_default())
+        }
+        (Cmd::ImportShares(..), _) => Cmd::ImportShares(import_files, language.unwrap_or_default()),
+        (Cmd::ExportShares(_), _) => Cmd::ExportShares(ShareSplit::new(
+            *matches
+                .get_one::<u8>("share-threshold")
+                .ok_or_else(|| anyhow!("share-threshold value"))?,
+            *matches
+                .get_one::<u8>("share-count")
+                .ok_or_else(|| anyhow!("share-count value"))?,
+        )?),
+        (_, [_, ..]) => {
+            return Err(anyhow!(
+                "import-file requires `--mnemonic import`, `import-shares` or `verify`"
+            ))
+        }
+        (Cmd::Create(_), []) => Cmd::Create(PhraseFormat::new(
+            *matches
+                .get_one::<usize>("word-count")
+                .ok_or_else(|| anyhow!("word-count value"))?,
+            language.unwrap_or_default(),
+        )?),
+        (Cmd::Export(_), []) => Cmd::Export(language),
+        (mnemonic_cmd, []) => mnemonic_cmd,
+    };
+    let plaintext_export = matches.get_flag("plaintext-export");
     let password_method = if matches.get_flag("no-password") {
         PasswordMethod::NoPassword
     } else {
         PasswordMethod::Prompt
     };
+    let tls = match (
+        layered(&matches, "tls-cert", file.tls_cert),
+        layered(&matches, "tls-key", file.tls_key),
+    ) {
+        (Some(cert), Some(key)) => Some(TlsConfig {
+            cert,
+            key,
+            client_ca: layered(&matches, "tls-client-ca", file.tls_client_ca),
+        }),
+        (None, None) => match layered(&matches, "tls-client-ca", file.tls_client_ca) {
+            Some(_) => return Err(anyhow!("tls-client-ca requires tls-cert and tls-key")),
+            None => None,
+        },
+        _ => return Err(anyhow!("tls-cert and tls-key must be set together")),
+    };
+    let unix_socket = match layered(&matches, "socket", file.socket) {
+        Some(_) if address_set => {
+            return Err(anyhow!("socket can't be set together with address or port"))
+        }
+        Some(path) => Some(UnixSocketConfig {
+            path,
+            mode: layered(
+                &matches,
+                "socket-mode",
+                file.socket_mode
+                    .as_deref()
+                    .map(parse_socket_mode)
+                    .transpose()
+                    .map_err(|err| anyhow!(err))?,
+            )
+            .ok_or_else(|| anyhow!("socket-mode value"))?,
+            uid: layered(&matches, "socket-uid", file.socket_uid),
+            gid: layered(&matches, "socket-gid", file.socket_gid),
+        }),
+        None => {
+            if ["socket-mode", "socket-uid", "socket-gid"]
+                .iter()
+                .any(|id| is_explicit(&matches, id))
+                || file.socket_mode.is_some()
+                || file.socket_uid.is_some()
+                || file.socket_gid.is_some()
+            {
+                return Err(anyhow!(
+                    "socket-mode, socket-uid and socket-gid require socket"
+                ));
+            }
+            None
+        }
+    };
+    let metrics_addr = match layered(&matches, "metrics-port", file.metrics_port) {
+        Some(metrics_port) => Some(addr(
+            &layered(&matches, "metrics-address", file.metrics_address)
+                .ok_or_else(|| anyhow!("metrics-address value"))?,
+            metrics_port,
+        )?),
+        None => {
+            if is_explicit(&matches, "metrics-address") || file.metrics_address.is_some() {
+                return Err(anyhow!("metrics-address requires metrics-port"));
+            }
+            None
+        }
+    };
+    let shutdown_grace_period = Duration::from_secs(
+        layered(
+            &matches,
+            "shutdown-grace-period",
+            file.shutdown_grace_period,
+        )
+        .ok_or_else(|| anyhow!("shutdown-grace-period value"))?,
+    );
+    let auth = layered(&matches, "auth", file.auth).unwrap_or_default();
+    let auth_tokens: Vec<AuthToken> = match matches.value_source("auth-token") {
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable) => matches
+            .get_many::<AuthToken>("auth-token")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        _ => file
+            .auth_tokens
+            .unwrap_or_default()
+            .iter()
+            .map(|label_and_token| parse_auth_token(label_and_token))
+            .collect::<Result<_, _>>()
+            .map_err(|err| anyhow!(err))?,
+    };
+    if !auth && !auth_tokens.is_empty() {
+        return Err(anyhow!("auth-token requires auth"));
+    }
+    let limits = Limits {
+        max_concurrent_requests: layered(
+            &matches,
+            "max-concurrent-requests",
+            file.max_concurrent_requests,
+        ),
+        rate_limit: match layered(&matches, "rate-limit", file.rate_limit) {
+            Some(per_second) => Some(RateLimit {
+                per_second,
+                burst: layered(&matches, "rate-limit-burst", file.rate_limit_burst)
+                    .ok_or_else(|| anyhow!("rate-limit-burst value"))?,
+            }),
+            None => {
+                if is_explicit(&matches, "rate-limit-burst") || file.rate_limit_burst.is_some() {
+                    return Err(anyhow!("rate-limit-burst requires rate-limit"));
+                }
+                None
+            }
+        },
+        crypto_threads: layered(&matches, "crypto-threads", file.crypto_threads),
+    };
+    let policy = layered(&matches, "policy", file.policy);
+    let token_cmd = match matches.subcommand() {
+        Some(("token", token_matches)) => Some(match token_matches.subcommand() {
+            Some(("add", add_matches)) => TokenCmd::Add(label(add_matches)?),
+            Some(("list", _)) => TokenCmd::List,
+            Some(("revoke", revoke_matches)) => TokenCmd::Revoke(label(revoke_matches)?),
+            _ => return Err(anyhow!("unknown token command")),
+        }),
+        _ => None,
+    };
+    let index_rebuild = match matches.subcommand() {
+        Some(("index", index_matches)) => match index_matches.subcommand() {
+            Some(("rebuild", rebuild_matches)) => {
+                rebuild_matches.get_one::<PathBuf>("key-uids").cloned()
+            }
+            _ => return Err(anyhow!("unknown index command")),
+        },
+        _ => None,
+    };
+    let audit_verify = match matches.subcommand() {
+        Some(("audit", audit_matches)) => match audit_matches.subcommand() {
+            Some(("verify", _)) => true,
+            _ => return Err(anyhow!("unknown audit command")),
+        },
+        _ => false,
+    };
+    let admin = match (
+        layered(&matches, "admin-port", file.admin_port),
+        layered(&matches, "admin-socket", file.admin_socket),
+    ) {
+        (Some(_), Some(_)) => return Err(anyhow!("admin-port and admin-socket are exclusive")),
+        // any local user can connect to a port, unlike to a socket of mode 0600
+        (Some(_), None) if !auth => return Err(anyhow!("admin-port requires auth")),
+        (Some(port), None) => Some(AdminListener::tcp(port)?),
+        (None, Some(path)) => Some(AdminListener::unix(path)),
+        (None, None) => None,
+    };
+    let audit_required =
+        layered(&matches, "audit-required", file.audit_required).unwrap_or_default();
+    let print_config = matches.get_flag("print-config");
 
     Ok(Config {
         ip,
         port,
         mnemonic_cmd,
+        plaintext_export,
         tofnd_path,
         password_method,
+        tls,
+        unix_socket,
+        metrics_addr,
+        shutdown_grace_period,
+        auth,
+        auth_tokens,
+        token_cmd,
+        index_rebuild,
+        audit_verify,
+        audit_required,
+        limits,
+        policy,
+        admin,
+        print_config,
     })
 }
+
+// value of `id` by precedence: command line, environment variable, config file, default
+fn layered<T>(matches: &ArgMatches, id: &str, file_value: Option<T>) -> Option<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if is_explicit(matches, id) {
+        matches.get_one::<T>(id).cloned()
+    } else {
+        file_value.or_else(|| matches.get_one::<T>(id).cloned())
+    }
+}
+
+// whether `id` was set on the command line or in the environment, rather than by its default
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+fn label(matches: &ArgMatches) -> TofndResult<String> {
+    Ok(matches
+        .get_one::<String>("label")
+        .ok_or_else(|| anyhow!("label value"))?
+        .clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, PoisonError};
+    use testdir::testdir;
+
+    // the environment is shared by all tests, so only one test parses at a time
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    // parse `args` with the config file `contents` in the tofnd directory
+    fn parse(dir: &Path, contents: &str, args: &[&str]) -> TofndResult<Config> {
+        parse_with_env(dir, contents, &[], args)
+    }
+
+    // parse `args` with the config file `contents` and the environment variables `env`
+    fn parse_with_env(
+        dir: &Path,
+        contents: &str,
+        env: &[(&str, &str)],
+        args: &[&str],
+    ) -> TofndResult<Config> {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        std::fs::write(dir.join("tofnd.toml"), contents).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        for (key, value) in env {
+            std::env::set_var(key, value);
+        }
+        let matches = command().and_then(|command| {
+            Ok(command
+                .try_get_matches_from(["tofnd", "--directory", dir].iter().chain(args.iter()))?)
+        });
+        for (key, _) in env {
+            std::env::remove_var(key);
+        }
+
+        config_from_matches(matches?)
+    }
+
+    fn labels(cfg: &Config) -> Vec<&str> {
+        cfg.auth_tokens
+            .iter()
+            .map(|token| token.label.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let dir = testdir!();
+
+        // the file overrides defaults, the environment overrides the file, and the command line
+        // overrides the environment
+        let env = [("TOFND_PORT", "50053")];
+        let cfg = parse(&dir, "", &[]).unwrap();
+        assert_eq!(cfg.port, 50051);
+        let cfg = parse(&dir, "port = 50052", &[]).unwrap();
+        assert_eq!(cfg.port, 50052);
+        let cfg = parse_with_env(&dir, "port = 50052", &env, &[]).unwrap();
+        assert_eq!(cfg.port, 50053);
+        let cfg = parse_with_env(&dir, "port = 50052", &env, &["--port", "50054"]).unwrap();
+        assert_eq!(cfg.port, 50054);
+
+        // the same holds for lists and flags
+        let file = "auth-tokens = [\"file:secret\"]";
+        let env = [(AUTH_TOKENS_ENV_VAR, "env:secret"), ("TOFND_AUTH", "true")];
+        let cfg = parse(&dir, &format!("auth = true\n{}", file), &[]).unwrap();
+        assert_eq!(labels(&cfg), ["file"]);
+        let cfg = parse_with_env(&dir, file, &env, &[]).unwrap();
+        assert!(cfg.auth);
+        assert_eq!(labels(&cfg), ["env"]);
+        let cfg = parse_with_env(&dir, file, &env, &["--auth-token", "cli:secret"]).unwrap();
+        assert_eq!(labels(&cfg), ["cli"]);
+    }
+
+    #[test]
+    fn test_dependent_options() {
+        let dir = testdir!();
+
+        // options that only apply to another one require it, wherever they are set
+        for (file, args) in [
+            ("", &["--socket-mode", "660"][..]),
+            ("socket-gid = 1000", &[]),
+            ("", &["--metrics-address", "0.0.0.0"]),
+            ("rate-limit-burst = 5", &[]),
+            ("", &["--rate-limit-burst", "5"]),
+            ("", &["--admin-port", "50052"]),
+            ("socket = \"tofnd.sock\"", &["--port", "50052"]),
+            ("address = \"127.0.0.1\"", &["--socket", "tofnd.sock"]),
+        ] {
+            assert!(parse(&dir, file, args).is_err());
+        }
+
+        assert!(parse(&dir, "auth = true", &["--admin-port", "50052"]).is_ok());
+        let cfg = parse(&dir, "rate-limit = 2.0", &["--rate-limit-burst", "5"]).unwrap();
+        assert_eq!(cfg.limits.rate_limit.unwrap().burst, 5);
+        let cfg = parse(&dir, "socket = \"tofnd.sock\"", &["--socket-mode", "660"]).unwrap();
+        assert_eq!(cfg.unix_socket.unwrap().mode, 0o660);
+    }
+
+    #[test]
+    fn test_delete_import_file() {
+        let dir = testdir!();
+        let args = ["--import-file", "mnemonic.txt", "--delete-import-file"];
+
+        let cfg = parse(&dir, "", &[&["-m", "import"][..], &args].concat()).unwrap();
+        assert!(matches!(
+            cfg.mnemonic_cmd,
+            Cmd::Import(Some(ImportFile { delete: true, .. }), _)
+        ));
+
+        // verify leaves the mnemonic file in place
+        assert!(parse(&dir, "", &[&["-m", "verify"][..], &args].concat()).is_err());
+    }
+
+    #[test]
+    fn test_print_config() {
+        let dir = testdir!();
+        let cfg = parse(
+            &dir,
+            "auth = true\nrate-limit = 2.0",
+            &["--print-config", "--auth-token", "vald:secret"],
+        )
+        .unwrap();
+        assert!(cfg.print_config);
+
+        // secrets are redacted
+        let printed = cfg.to_redacted_toml().unwrap();
+        assert!(!printed.contains("secret"));
+
+        // a printed redacted token is never accepted as a token
+        assert!(parse(&dir, &printed, &[]).is_err());
+
+        // without tokens, the printed configuration is a config file with the same settings
+        let cfg = parse(&dir, "auth = true\nrate-limit = 2.0", &[]).unwrap();
+        let printed = cfg.to_redacted_toml().unwrap();
+        let reparsed = parse(&dir, &printed, &[]).unwrap();
+        assert_eq!(reparsed.to_redacted_toml().unwrap(), printed);
+    }
+}
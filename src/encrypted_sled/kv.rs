@@ -197,7 +197,7 @@ impl EncryptedDb {
         self.kv.was_recovered()
     }
 
-    #[cfg(test)]
+    /// Synchronously flushes all dirty IO buffers to disk, returning the number of bytes flushed.
     pub fn flush(&self) -> EncryptedDbResult<usize> {
         Ok(self.kv.flush()?)
     }
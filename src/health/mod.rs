@@ -0,0 +1,200 @@
+//! Standard gRPC health checking service: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+//!
+//! The multisig service is reported as NOT_SERVING until the mnemonic has been
+//! loaded and the kv store actor responds to requests. It returns to
+//! NOT_SERVING if the kv store actor stops.
+
+use tonic_health::{
+    pb::health_server::{Health, HealthServer},
+    server::HealthReporter,
+    ServingStatus,
+};
+
+use crate::{
+    kv_manager::KvManager, multisig::service::MultisigService,
+    proto::multisig_server::MultisigServer,
+};
+
+// logging
+use tracing::{error, info, warn};
+
+/// The empty service name reports the overall health of the server
+const SERVER_HEALTH: &str = "";
+
+/// Set the health status of the server and of the multisig service
+async fn set_status(reporter: &mut HealthReporter, status: ServingStatus) {
+    reporter.set_service_status(SERVER_HEALTH, status).await;
+    match status {
+        ServingStatus::Serving => {
+            reporter
+                .set_serving::<MultisigServer<MultisigService>>()
+                .await
+        }
+        _ => {
+            reporter
+                .set_not_serving::<MultisigServer<MultisigService>>()
+                .await
+        }
+    }
+}
+
+/// Create a health service that reports NOT_SERVING until [monitor_kv_manager] is called
+pub async fn health_service() -> (HealthReporter, HealthServer<impl Health>) {
+    let (mut reporter, service) = tonic_health::server::health_reporter();
+    set_status(&mut reporter, ServingStatus::NotServing).await;
+    (reporter, service)
+}
+
+/// Report SERVING once `kv_manager` responds, and switch back to NOT_SERVING if its kv store actor stops.
+/// `kv_manager` is expected to have successfully handled [crate::mnemonic::Cmd::Existing].
+pub async fn monitor_kv_manager(mut reporter: HealthReporter, kv_manager: KvManager) {
+    // probe the kv store actor before reporting SERVING
+    if let Err(err) = kv_manager.seed_count().await {
+        error!(
+            "kv store is not responding, health status remains NOT_SERVING: {}",
+            err
+        );
+        return;
+    }
+    set_status(&mut reporter, ServingStatus::Serving).await;
+    info!("health status set to SERVING");
+
+    tokio::spawn(async move {
+        kv_manager.kv().closed().await;
+        warn!("kv store stopped, health status set to NOT_SERVING");
+        set_status(&mut reporter, ServingStatus::NotServing).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        addr,
+        encrypted_sled::get_test_password,
+        tests::{DEFAULT_TEST_IP, DEFAULT_TEST_PORT},
+    };
+    use testdir::testdir;
+    use tokio::{net::TcpListener, sync::oneshot};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Channel, Streaming};
+    use tonic_health::pb::{
+        health_check_response::ServingStatus as ResponseStatus, health_client::HealthClient,
+        HealthCheckRequest, HealthCheckResponse,
+    };
+    use tracing_test::traced_test;
+
+    const MULTISIG_SERVICE: &str = "tofnd.Multisig";
+
+    async fn check(client: &mut HealthClient<Channel>, service: &str) -> ResponseStatus {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let status = client.check(request).await.unwrap().into_inner().status;
+        ResponseStatus::try_from(status).unwrap()
+    }
+
+    // serve the health service; returns its reporter, a client and the shutdown channel
+    async fn spin_health_service() -> (HealthReporter, HealthClient<Channel>, oneshot::Sender<()>) {
+        let (reporter, service) = health_service().await;
+
+        let incoming = TcpListener::bind(addr(DEFAULT_TEST_IP, DEFAULT_TEST_PORT).unwrap())
+            .await
+            .unwrap();
+        let server_addr = incoming.local_addr().unwrap();
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(TcpListenerStream::new(incoming), async {
+                    shutdown_receiver.await.unwrap();
+                })
+                .await
+                .unwrap();
+        });
+        let channel = Channel::from_shared(format!("http://{}", server_addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        (reporter, HealthClient::new(channel), shutdown_sender)
+    }
+
+    async fn create_kv_manager() -> KvManager {
+        KvManager::new(testdir!(), get_test_password())
+            .unwrap()
+            .with_export_mode(Some(crate::mnemonic::ExportMode::Plaintext))
+            .handle_mnemonic(&crate::mnemonic::Cmd::Create(
+                crate::mnemonic::PhraseFormat::default(),
+            ))
+            .await
+            .unwrap()
+    }
+
+    async fn next_status(statuses: &mut Streaming<HealthCheckResponse>) -> ResponseStatus {
+        let status = statuses.message().await.unwrap().unwrap().status;
+        ResponseStatus::try_from(status).unwrap()
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_health_status() {
+        let (reporter, mut client, shutdown_sender) = spin_health_service().await;
+
+        // not serving before the mnemonic is loaded
+        assert_eq!(
+            check(&mut client, SERVER_HEALTH).await,
+            ResponseStatus::NotServing
+        );
+        assert_eq!(
+            check(&mut client, MULTISIG_SERVICE).await,
+            ResponseStatus::NotServing
+        );
+
+        monitor_kv_manager(reporter, create_kv_manager().await).await;
+
+        assert_eq!(
+            check(&mut client, SERVER_HEALTH).await,
+            ResponseStatus::Serving
+        );
+        assert_eq!(
+            check(&mut client, MULTISIG_SERVICE).await,
+            ResponseStatus::Serving
+        );
+
+        shutdown_sender.send(()).unwrap();
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_health_status_kv_stopped() {
+        let (reporter, mut client, shutdown_sender) = spin_health_service().await;
+
+        // run the kv store actor on a runtime of its own, so that the test can stop it
+        let kv_runtime = tokio::runtime::Runtime::new().unwrap();
+        let kv_manager = kv_runtime.spawn(create_kv_manager()).await.unwrap();
+        monitor_kv_manager(reporter, kv_manager).await;
+
+        let request = HealthCheckRequest {
+            service: MULTISIG_SERVICE.to_string(),
+        };
+        let mut statuses = client.watch(request).await.unwrap().into_inner();
+        assert_eq!(next_status(&mut statuses).await, ResponseStatus::Serving);
+
+        // the status may be repeated before it changes
+        kv_runtime.shutdown_background();
+        let status = loop {
+            match next_status(&mut statuses).await {
+                ResponseStatus::Serving => continue,
+                status => break status,
+            }
+        };
+        assert_eq!(status, ResponseStatus::NotServing);
+        assert_eq!(
+            check(&mut client, SERVER_HEALTH).await,
+            ResponseStatus::NotServing
+        );
+
+        shutdown_sender.send(()).unwrap();
+    }
+}
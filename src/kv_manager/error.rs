@@ -30,6 +30,8 @@ pub enum KvError {
     DeleteErr(InnerKvError),
     #[error("Exits Error: {0}")]
     ExistsErr(InnerKvError),
+    #[error("Flush Error: {0}")]
+    FlushErr(InnerKvError),
 }
 pub type KvResult<Success> = Result<Success, KvError>;
 
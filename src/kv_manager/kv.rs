@@ -1,11 +1,17 @@
 //! Public API for kvstore operations
 //! Errors are mapped to [super::error::KvError]
 
-use crate::encrypted_sled::{self, Password};
+use crate::{
+    encrypted_sled::{self, Password},
+    metrics::KV_QUEUE_DEPTH,
+};
 
 use super::{
     error::{KvError::*, KvResult},
-    sled_bindings::{handle_delete, handle_exists, handle_get, handle_put, handle_reserve},
+    sled_bindings::{
+        handle_delete, handle_exists, handle_flush, handle_get, handle_insert, handle_put,
+        handle_replace, handle_reserve,
+    },
     types::{
         Command::{self, *},
         KeyReservation, DEFAULT_KV_NAME, DEFAULT_KV_PATH,
@@ -56,29 +62,25 @@ where
     /// Returns [ReserveErr] or [SendErr] on failure.
     pub async fn reserve_key(&self, key: String) -> KvResult<KeyReservation> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.sender
-            .send(ReserveKey { key, resp: resp_tx })
-            .map_err(|err| SendErr(err.to_string()))?;
+        self.send(ReserveKey { key, resp: resp_tx })?;
         resp_rx.await?.map_err(ReserveErr)
     }
 
     /// Unreserves an existing reservation
     #[allow(dead_code)]
     pub async fn unreserve_key(&self, reservation: KeyReservation) {
-        let _ = self.sender.send(UnreserveKey { reservation });
+        let _ = self.send(UnreserveKey { reservation });
     }
 
     /// Puts a new value given a [super::types::KeyReservation]
     /// Returns [PutErr] or [SendErr] on failure.
     pub async fn put(&self, reservation: KeyReservation, value: V) -> KvResult<()> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.sender
-            .send(Put {
-                reservation,
-                value,
-                resp: resp_tx,
-            })
-            .map_err(|e| SendErr(e.to_string()))?;
+        self.send(Put {
+            reservation,
+            value,
+            resp: resp_tx,
+        })?;
         resp_rx.await?.map_err(PutErr)
     }
 
@@ -86,12 +88,10 @@ where
     /// Returns [GetErr] or [SendErr] on failure.
     pub async fn get(&self, key: &str) -> KvResult<V> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.sender
-            .send(Get {
-                key: key.to_string(),
-                resp: resp_tx,
-            })
-            .map_err(|e| SendErr(e.to_string()))?;
+        self.send(Get {
+            key: key.to_string(),
+            resp: resp_tx,
+        })?;
         resp_rx.await?.map_err(GetErr)
     }
 
@@ -99,27 +99,71 @@ where
     /// Returns [DeleteErr] or [SendErr] on failure.
     pub async fn delete(&self, key: &str) -> KvResult<()> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.sender
-            .send(Delete {
-                key: key.to_string(),
-                resp: resp_tx,
-            })
-            .map_err(|e| SendErr(e.to_string()))?;
+        self.send(Delete {
+            key: key.to_string(),
+            resp: resp_tx,
+        })?;
         resp_rx.await?.map_err(DeleteErr)
     }
 
+    /// Sets the value of an unreserved key, replacing its current value if any, atomically
+    /// Returns [PutErr] or [SendErr] on failure.
+    pub async fn replace(&self, key: &str, value: V) -> KvResult<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send(Replace {
+            key: key.to_string(),
+            value,
+            resp: resp_tx,
+        })?;
+        resp_rx.await?.map_err(PutErr)
+    }
+
+    /// Sets the value of a key that doesn't exist, atomically.
+    /// Returns whether the value was set, or [PutErr] or [SendErr] on failure.
+    pub async fn insert(&self, key: &str, value: V) -> KvResult<bool> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send(Insert {
+            key: key.to_string(),
+            value,
+            resp: resp_tx,
+        })?;
+        resp_rx.await?.map_err(PutErr)
+    }
+
     /// Checks if a key exists in the kvstore
     /// Returns [ExistsErr] or [SendErr] on failure.
     pub async fn exists(&self, key: &str) -> KvResult<bool> {
         let (resp_tx, resp_rx) = oneshot::channel();
-        self.sender
-            .send(Exists {
-                key: key.to_string(),
-                resp: resp_tx,
-            })
-            .map_err(|e| SendErr(e.to_string()))?;
+        self.send(Exists {
+            key: key.to_string(),
+            resp: resp_tx,
+        })?;
         resp_rx.await?.map_err(ExistsErr)
     }
+
+    /// Flushes the kvstore to disk
+    /// Returns [FlushErr] or [SendErr] on failure.
+    pub async fn flush(&self) -> KvResult<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send(Flush { resp: resp_tx })?;
+        resp_rx.await?.map_err(FlushErr)
+    }
+
+    /// Sends a command to the kv store actor and tracks it in the queue depth metric.
+    /// Returns [SendErr] on failure.
+    fn send(&self, cmd: Command<V>) -> KvResult<()> {
+        // increment before sending, so that the actor never decrements first
+        KV_QUEUE_DEPTH.inc();
+        self.sender.send(cmd).map_err(|err| {
+            KV_QUEUE_DEPTH.dec();
+            SendErr(err.to_string())
+        })
+    }
+
+    /// Completes when the kv store actor has stopped and can no longer serve commands
+    pub async fn closed(&self) {
+        self.sender.closed().await
+    }
 }
 
 /// Returns the db with name `db_name`, or creates a new if such DB does not exist
@@ -158,6 +202,8 @@ async fn kv_cmd_handler<V: 'static + Serialize + DeserializeOwned>(
     // if resp.send() fails then log a warning and continue
     // see discussion https://github.com/axelarnetwork/tofnd/pull/15#discussion_r595426775
     while let Some(cmd) = rx.recv().await {
+        KV_QUEUE_DEPTH.dec();
+
         // TODO better error handling and logging: we should log when `handle_*` fails
         // TODO refactor repeated code
         match cmd {
@@ -193,6 +239,21 @@ async fn kv_cmd_handler<V: 'static + Serialize + DeserializeOwned>(
                     warn!("receiver dropped");
                 }
             }
+            Replace { key, value, resp } => {
+                if resp.send(handle_replace(&kv, key, value)).is_err() {
+                    warn!("receiver dropped");
+                }
+            }
+            Insert { key, value, resp } => {
+                if resp.send(handle_insert(&kv, key, value)).is_err() {
+                    warn!("receiver dropped");
+                }
+            }
+            Flush { resp } => {
+                if resp.send(handle_flush(&kv)).is_err() {
+                    warn!("receiver dropped");
+                }
+            }
         }
     }
     info!("kv_manager stop");
@@ -80,6 +80,52 @@ where
     Ok(())
 }
 
+/// Inserts a value to an unreserved key, replacing its current value if any, in a single write.
+/// Returns [SledErr] of [LogicalErr] on failure.
+pub(super) fn handle_replace<V>(kv: &encrypted_sled::Db, key: String, value: V) -> InnerKvResult<()>
+where
+    V: Serialize,
+{
+    // check if key holds the default reserve value. If yes, can't replace it.
+    if kv.get(&key)? == Some(sled::IVec::from(DEFAULT_RESERVE)) {
+        return Err(LogicalErr(format!(
+            "can't replace reserved key <{}> in kv store.",
+            key
+        )));
+    }
+
+    // convert value into bytes
+    let bytes = serialize(&value).map_err(|_| SerializationErr)?;
+
+    // insert new value
+    kv.insert(&key, bytes)?;
+
+    Ok(())
+}
+
+/// Inserts a value to a missing key, in a single write. Existing and reserved keys are kept.
+/// Returns whether the value was inserted, or [SledErr] on failure.
+pub(super) fn handle_insert<V>(
+    kv: &encrypted_sled::Db,
+    key: String,
+    value: V,
+) -> InnerKvResult<bool>
+where
+    V: Serialize,
+{
+    if kv.contains_key(&key)? {
+        return Ok(false);
+    }
+
+    // convert value into bytes
+    let bytes = serialize(&value).map_err(|_| SerializationErr)?;
+
+    // insert new value
+    kv.insert(&key, bytes)?;
+
+    Ok(true)
+}
+
 /// Get the value of an existing key.
 /// Returns [SledErr] of [LogicalErr] on failure.
 pub(super) fn handle_get<V>(kv: &encrypted_sled::Db, key: String) -> InnerKvResult<V>
@@ -108,3 +154,10 @@ pub(super) fn handle_exists(kv: &encrypted_sled::Db, key: &str) -> InnerKvResult
         ))
     })
 }
+
+/// Flushes all dirty IO buffers of the kvstore to disk.
+/// Returns [SledErr] on failure.
+pub(super) fn handle_flush(kv: &encrypted_sled::Db) -> InnerKvResult<()> {
+    kv.flush()?;
+    Ok(())
+}
@@ -2,7 +2,9 @@
 
 use super::{
     error::InnerKvError::LogicalErr,
-    sled_bindings::{handle_exists, handle_get, handle_put, handle_reserve},
+    sled_bindings::{
+        handle_exists, handle_get, handle_insert, handle_put, handle_replace, handle_reserve,
+    },
     types::{KeyReservation, DEFAULT_RESERVE},
 };
 use crate::encrypted_sled;
@@ -121,6 +123,56 @@ fn put_failure_put_twice() {
     clean_up(kv_name.to_str().unwrap(), kv);
 }
 
+#[test]
+fn replace_success() {
+    let kv_name = testdir!();
+    let kv = open_with_test_password(&kv_name).unwrap();
+
+    let key: String = "key".to_string();
+    // replace inserts missing keys and overwrites existing values
+    handle_replace(&kv, key.clone(), "value").unwrap();
+    handle_replace(&kv, key.clone(), "value2").unwrap();
+    assert_eq!(handle_get::<String>(&kv, key).unwrap(), "value2");
+
+    clean_up(kv_name.to_str().unwrap(), kv);
+}
+
+#[test]
+fn replace_failure_reserved() {
+    let kv_name = testdir!();
+    let kv = open_with_test_password(&kv_name).unwrap();
+
+    let key: String = "key".to_string();
+    handle_reserve(&kv, key.clone()).unwrap();
+
+    let err = handle_replace(&kv, key.clone(), "value").err().unwrap();
+    assert!(matches!(err, LogicalErr(_)));
+    // the reservation is kept
+    assert!(kv.get(&key).unwrap().unwrap() == DEFAULT_RESERVE);
+
+    clean_up(kv_name.to_str().unwrap(), kv);
+}
+
+#[test]
+fn insert_success() {
+    let kv_name = testdir!();
+    let kv = open_with_test_password(&kv_name).unwrap();
+
+    let key: String = "key".to_string();
+    // insert only sets missing keys
+    assert!(handle_insert(&kv, key.clone(), "value").unwrap());
+    assert!(!handle_insert(&kv, key.clone(), "value2").unwrap());
+    assert_eq!(handle_get::<String>(&kv, key).unwrap(), "value");
+
+    // reserved keys are kept
+    let key: String = "reserved".to_string();
+    handle_reserve(&kv, key.clone()).unwrap();
+    assert!(!handle_insert(&kv, key.clone(), "value").unwrap());
+    assert!(kv.get(&key).unwrap().unwrap() == DEFAULT_RESERVE);
+
+    clean_up(kv_name.to_str().unwrap(), kv);
+}
+
 #[test]
 fn get_success() {
     let kv_name = testdir!();
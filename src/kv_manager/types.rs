@@ -53,4 +53,17 @@ pub(super) enum Command<V> {
         key: String,
         resp: Responder<()>,
     },
+    Replace {
+        key: String,
+        value: V,
+        resp: Responder<()>,
+    },
+    Insert {
+        key: String,
+        value: V,
+        resp: Responder<bool>,
+    },
+    Flush {
+        resp: Responder<()>,
+    },
 }
@@ -1,9 +1,10 @@
-use std::{convert::TryFrom, path::PathBuf};
+use std::{convert::TryFrom, path::PathBuf, sync::Arc};
 use tofn::sdk::api::{deserialize, serialize};
+use tokio::sync::RwLock;
 
 use crate::{
     encrypted_sled::Password,
-    mnemonic::{Entropy, FileIo},
+    mnemonic::{ExportMode, FileIo, Language, StoredMnemonic},
 };
 
 use super::{
@@ -16,6 +17,8 @@ use super::{
 pub struct KvManager {
     kv: Kv<KvValue>,
     io: FileIo,
+    /// held for writing while mnemonics are modified and for reading while they are looked up
+    mnemonic_lock: Arc<RwLock<()>>,
 }
 
 impl KvManager {
@@ -23,31 +26,54 @@ impl KvManager {
         Ok(KvManager {
             kv: Kv::<KvValue>::new(root.clone(), password)?,
             io: FileIo::new(root),
+            mnemonic_lock: Arc::new(RwLock::new(())),
         })
     }
+    /// Write mnemonic exports with `export_mode`; exports fail if [None]
+    pub fn with_export_mode(self, export_mode: Option<ExportMode>) -> Self {
+        Self {
+            io: self.io.with_export_mode(export_mode),
+            ..self
+        }
+    }
     pub fn kv(&self) -> &Kv<KvValue> {
         &self.kv
     }
     pub fn io(&self) -> &FileIo {
         &self.io
     }
+    pub fn mnemonic_lock(&self) -> &RwLock<()> {
+        &self.mnemonic_lock
+    }
+    /// Flush the kv store to disk
+    pub async fn flush(&self) -> KvResult<()> {
+        self.kv.flush().await
+    }
 }
 
 /// Value type stored in the kv-store
 type KvValue = Vec<u8>;
 
-/// Create Entropy from KvValue
-impl TryFrom<KvValue> for Entropy {
+/// Create StoredMnemonic from KvValue.
+/// Mnemonics stored before languages were supported only hold their entropy, and are English.
+impl TryFrom<KvValue> for StoredMnemonic {
     type Error = InnerKvError;
     fn try_from(v: KvValue) -> Result<Self, Self::Error> {
-        deserialize(&v).ok_or(InnerKvError::DeserializationErr)
+        deserialize(&v)
+            .or_else(|| {
+                deserialize(&v).map(|entropy| StoredMnemonic {
+                    entropy,
+                    language: Language::English,
+                })
+            })
+            .ok_or(InnerKvError::DeserializationErr)
     }
 }
 
-/// Create KvValue from Entropy
-impl TryFrom<Entropy> for KvValue {
+/// Create KvValue from StoredMnemonic
+impl TryFrom<StoredMnemonic> for KvValue {
     type Error = InnerKvError;
-    fn try_from(v: Entropy) -> Result<Self, Self::Error> {
+    fn try_from(v: StoredMnemonic) -> Result<Self, Self::Error> {
         serialize(&v).map_err(|_| InnerKvError::SerializationErr)
     }
 }
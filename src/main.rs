@@ -1,16 +1,27 @@
-use multisig::service::MultisigService;
+use audit::AuditLog;
+use auth::Authenticator;
+use multisig::{policy::Policy, service::MultisigService};
 use proto::multisig_server::MultisigServer;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
-use tokio_stream::wrappers::TcpListenerStream;
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::oneshot,
+};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
 
+mod admin;
+mod audit;
+mod auth;
 mod encrypted_sled;
+mod health;
 mod kv_manager;
+mod metrics;
 mod mnemonic;
 mod multisig;
 
 // gather logs; need to set RUST_LOG=info
-use tracing::{info, span, Level};
+use tracing::{error, info, span, warn, Level};
 
 // error handling
 pub type TofndResult<Success> = anyhow::Result<Success>;
@@ -18,12 +29,17 @@ pub type TofndResult<Success> = anyhow::Result<Success>;
 // protocol buffers via tonic: https://github.com/hyperium/tonic/blob/master/examples/helloworld-tutorial.md#writing-our-server
 pub mod proto {
     tonic::include_proto!("tofnd");
+
+    pub mod admin {
+        tonic::include_proto!("tofnd.admin");
+    }
 }
 
 mod config;
 use config::parse_args;
 
 use crate::kv_manager::KvManager;
+use crate::mnemonic::ExportMode;
 
 fn set_up_logs() {
     // enable only tofnd and tofn debug logs - disable serde, tonic, tokio, etc.
@@ -43,38 +59,171 @@ fn set_up_logs() {
 async fn main() -> TofndResult<()> {
     set_up_logs(); // can't print any logs until they're set up
     let cfg = parse_args()?;
+    if cfg.print_config {
+        print!("{}", cfg.to_redacted_toml()?);
+        return Ok(());
+    }
+    if cfg.audit_verify {
+        let records = audit::verify(&cfg.tofnd_path)?;
+        println!("audit log of {} records is intact", records);
+        return Ok(());
+    }
     let socket_address = addr(&cfg.ip, cfg.port)?;
 
     // immediately read an encryption password from stdin
     let password = cfg.password_method.execute()?;
 
+    // then the export passphrase, if the mnemonic command writes an export file
+    let export_mode = if cfg.plaintext_export {
+        Some(ExportMode::Plaintext)
+    } else if cfg.mnemonic_cmd.writes_export() {
+        Some(ExportMode::prompt()?)
+    } else {
+        None
+    };
+
     // set up span for logs
     let main_span = span!(Level::INFO, "main");
     let _enter = main_span.enter();
     let cmd = cfg.mnemonic_cmd.clone();
 
+    // report NOT_SERVING until the mnemonic is loaded and the kv store responds
+    let (health_reporter, health_service) = health::health_service().await;
+
     // this step takes a long time due to password-based decryption
-    let kv_manager = KvManager::new(cfg.tofnd_path.clone(), password)?
-        .handle_mnemonic(&cfg.mnemonic_cmd)
-        .await?;
+    let kv_manager =
+        KvManager::new(cfg.tofnd_path.clone(), password)?.with_export_mode(export_mode);
+
+    if let Some(token_cmd) = &cfg.token_cmd {
+        kv_manager.handle_token_cmd(token_cmd).await?;
+        kv_manager.flush().await?;
+        info!("Tofnd exited after using token command <{:?}>.", token_cmd);
+        return Ok(());
+    }
+
+    if let Some(key_uids_path) = &cfg.index_rebuild {
+        let key_uids: Vec<String> = std::fs::read_to_string(key_uids_path)?
+            .lines()
+            .map(str::trim)
+            .filter(|key_uid| !key_uid.is_empty())
+            .map(str::to_string)
+            .collect();
+        let indexed = kv_manager.rebuild_key_index(&key_uids).await?;
+        kv_manager.flush().await?;
+        info!(
+            "Tofnd exited after indexing {} keys of {} key uids.",
+            indexed,
+            key_uids.len()
+        );
+        return Ok(());
+    }
+
+    let kv_manager = kv_manager.handle_mnemonic(&cfg.mnemonic_cmd).await?;
 
     if cmd.exit_after_cmd() {
         info!("Tofnd exited after using command <{:?}>. Run `./tofnd -m existing` to execute gRPC daemon.", cmd);
         return Ok(());
     }
 
-    let service = MultisigServer::new(MultisigService::new(kv_manager));
+    if let Some(metrics_addr) = cfg.metrics_addr {
+        tokio::spawn(metrics::serve(TcpListener::bind(metrics_addr).await?));
+    }
+
+    let authenticator = if cfg.auth {
+        let mut auth_tokens = kv_manager.auth_tokens().await?;
+        auth_tokens.extend(cfg.auth_tokens.iter().cloned());
+        info!(
+            "tofnd requires bearer tokens from {} clients",
+            auth_tokens.len()
+        );
+        Authenticator::new(auth_tokens)?
+    } else {
+        Authenticator::disabled()
+    };
+
+    let policy = Policy::load(cfg.policy.clone(), cfg.auth)?;
+    if cfg.policy.is_some() {
+        tokio::spawn(reload_policy_on_sighup(policy.clone()));
+    }
 
-    let incoming = TcpListener::bind(socket_address).await?;
-    info!(
-        "tofnd listen addr {:?}, use ctrl+c to shutdown",
-        incoming.local_addr()?
+    health::monitor_kv_manager(health_reporter, kv_manager.clone()).await;
+    let audit_log = AuditLog::open(&cfg.tofnd_path, cfg.audit_required)?;
+    let service = MultisigServer::with_interceptor(
+        MultisigService::new(kv_manager.clone(), cfg.limits)?
+            .with_policy(policy)
+            .with_audit_log(audit_log.clone()),
+        authenticator.clone(),
     );
 
-    tonic::transport::Server::builder()
-        .add_service(service)
-        .serve_with_incoming_shutdown(TcpListenerStream::new(incoming), shutdown_signal())
-        .await?;
+    let mut server = tonic::transport::Server::builder();
+    if let Some(tls) = &cfg.tls {
+        server = server.tls_config(tls.server_tls_config()?)?;
+        info!(
+            "tofnd serving over TLS (client certificates required: {})",
+            tls.client_ca.is_some()
+        );
+    }
+    let router = server.add_service(health_service).add_service(service);
+
+    // stops the admin service once the multisig server is drained
+    let (admin_shutdown_sender, admin_shutdown_receiver) = oneshot::channel();
+    let admin_server = match &cfg.admin {
+        Some(admin) => Some(
+            admin::spawn(
+                admin,
+                kv_manager.clone(),
+                authenticator.clone(),
+                admin_shutdown_receiver,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    // notifies `drain` that the server stopped accepting new requests
+    let (drain_sender, drain_receiver) = oneshot::channel();
+    let shutdown = async move {
+        shutdown_signal().await;
+        let _ = drain_sender.send(());
+    };
+
+    match &cfg.unix_socket {
+        Some(unix_socket) => {
+            // the socket file is removed when `_socket_file` is dropped, also if serving fails
+            let (incoming, _socket_file) = unix_socket.bind()?;
+            info!(
+                "tofnd listen unix socket {:?}, use ctrl+c to shutdown",
+                unix_socket.path
+            );
+
+            let server =
+                router.serve_with_incoming_shutdown(UnixListenerStream::new(incoming), shutdown);
+            drain(server, drain_receiver, cfg.shutdown_grace_period).await?;
+        }
+        None => {
+            let incoming = TcpListener::bind(socket_address).await?;
+            info!(
+                "tofnd listen addr {:?}, use ctrl+c to shutdown",
+                incoming.local_addr()?
+            );
+
+            let server =
+                router.serve_with_incoming_shutdown(TcpListenerStream::new(incoming), shutdown);
+            drain(server, drain_receiver, cfg.shutdown_grace_period).await?;
+        }
+    }
+
+    if let Some(admin_server) = admin_server {
+        let _ = admin_shutdown_sender.send(());
+        admin_server.await??;
+    }
+
+    // write the records of the drained requests
+    audit_log.flush().await?;
+
+    // persist everything written to the kv store before exiting
+    kv_manager.flush().await?;
+    info!("tofnd kv store flushed, exiting");
 
     Ok(())
 }
@@ -86,14 +235,58 @@ fn addr(ip: &str, port: u16) -> TofndResult<SocketAddr> {
         .map_err(|err| anyhow::anyhow!(err))
 }
 
+/// Drive `server` to completion. Once `shutdown_started` fires, `server` no longer accepts new
+/// requests; in-flight requests are given `grace_period` to complete before they are dropped.
+async fn drain<F>(
+    server: F,
+    shutdown_started: oneshot::Receiver<()>,
+    grace_period: Duration,
+) -> TofndResult<()>
+where
+    F: Future<Output = Result<(), tonic::transport::Error>>,
+{
+    tokio::pin!(server);
+
+    tokio::select! {
+        res = &mut server => return Ok(res?),
+        Ok(()) = shutdown_started => {}
+    }
+
+    match tokio::time::timeout(grace_period, server).await {
+        Ok(res) => Ok(res?),
+        Err(_) => {
+            warn!(
+                "in-flight requests did not complete within the {:?} grace period, dropping them",
+                grace_period
+            );
+            Ok(())
+        }
+    }
+}
+
 // graceful shutdown https://hyper.rs/guides/server/graceful-shutdown/
 // can't use Result<> here because `serve_with_incoming_shutdown` expects F: Future<Output = ()>,
 async fn shutdown_signal() {
-    // Wait for the CTRL+C signal
-    tokio::signal::ctrl_c()
-        .await
-        .expect("failed to install CTRL+C signal handler");
-    info!("tofnd shutdown signal received");
+    // Docker and systemd stop services with SIGTERM, ctrl+c sends SIGINT
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    let mut sigint = signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("tofnd SIGTERM received"),
+        _ = sigint.recv() => info!("tofnd SIGINT received"),
+    }
+    info!("tofnd shutdown signal received, draining in-flight requests");
+}
+
+/// Reload the signing policy on every SIGHUP; an invalid policy file keeps the current rules
+async fn reload_policy_on_sighup(policy: Policy) {
+    let mut sighup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+
+    while sighup.recv().await.is_some() {
+        if let Err(err) = policy.reload() {
+            error!("tofnd signing policy not reloaded: {}", err);
+        }
+    }
 }
 
 #[cfg(test)]
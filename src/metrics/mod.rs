@@ -0,0 +1,179 @@
+//! Prometheus metrics of the multisig service and the kv store.
+//! Metrics are served in the Prometheus text format at `/metrics` on a separate HTTP port.
+
+use std::{convert::Infallible, time::Instant};
+
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{HeaderValue, CONTENT_TYPE},
+    server::conn::http1,
+    service::service_fn,
+    Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder, TEXT_FORMAT,
+};
+use tokio::net::TcpListener;
+
+use crate::proto::Algorithm;
+
+// logging
+use tracing::{info, warn};
+
+/// path at which metrics are served
+const METRICS_PATH: &str = "/metrics";
+
+/// labels of per-request metrics
+const REQUEST_LABELS: &[&str] = &["rpc", "algorithm", "outcome"];
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "tofnd_requests_total",
+        "Number of multisig requests handled, by rpc, algorithm and outcome",
+        REQUEST_LABELS
+    )
+    .expect("failed to register tofnd_requests_total");
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "tofnd_request_duration_seconds",
+        "Latency of multisig requests, by rpc, algorithm and outcome",
+        REQUEST_LABELS
+    )
+    .expect("failed to register tofnd_request_duration_seconds");
+    /// number of mnemonics stored in the kv store
+    pub static ref MNEMONIC_COUNT: IntGauge = register_int_gauge!(
+        "tofnd_mnemonic_count",
+        "Number of mnemonics stored in the kv store"
+    )
+    .expect("failed to register tofnd_mnemonic_count");
+    /// number of commands waiting to be processed by the kv store actor
+    pub static ref KV_QUEUE_DEPTH: IntGauge = register_int_gauge!(
+        "tofnd_kv_queue_depth",
+        "Number of commands waiting to be processed by the kv store actor"
+    )
+    .expect("failed to register tofnd_kv_queue_depth");
+    /// lookups of derived key pairs in the key pair cache, by result (`hit` or `miss`)
+    pub static ref KEY_CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "tofnd_key_cache_lookups_total",
+        "Number of lookups in the derived key pair cache, by result",
+        &["result"]
+    )
+    .expect("failed to register tofnd_key_cache_lookups_total");
+}
+
+/// Multisig gRPCs
+#[derive(Clone, Copy, Debug)]
+pub enum Rpc {
+    KeyPresence,
+    Keygen,
+    Sign,
+    BatchSign,
+}
+
+impl Rpc {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::KeyPresence => "key_presence",
+            Self::Keygen => "keygen",
+            Self::Sign => "sign",
+            Self::BatchSign => "batch_sign",
+        }
+    }
+}
+
+/// Record the outcome and the latency of a multisig request that started at `started`
+pub fn observe_request(rpc: Rpc, algorithm: i32, outcome: &str, started: Instant) {
+    let algorithm = Algorithm::try_from(algorithm)
+        .map(|algorithm| algorithm.as_str_name())
+        .unwrap_or("INVALID");
+    let labels = [rpc.as_str(), algorithm, outcome];
+
+    REQUESTS.with_label_values(&labels).inc();
+    REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Serve metrics on `listener` until the task is dropped
+pub async fn serve(listener: TcpListener) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("tofnd metrics listen addr {:?}", addr);
+    }
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("metrics server cannot accept connection: {}", err);
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service_fn(handle_request))
+                .await
+            {
+                warn!("metrics server connection failed: {}", err);
+            }
+        });
+    }
+}
+
+async fn handle_request(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Full::new(Bytes::new()));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    Ok(match encode() {
+        Ok(body) => {
+            let mut response = Response::new(Full::new(Bytes::from(body)));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(TEXT_FORMAT));
+            response
+        }
+        Err(err) => {
+            warn!("cannot encode metrics: {}", err);
+            let mut response = Response::new(Full::new(Bytes::new()));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    })
+}
+
+/// Encode all registered metrics in the Prometheus text format
+fn encode() -> prometheus::Result<Vec<u8>> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_request_metrics() {
+        observe_request(
+            Rpc::Sign,
+            Algorithm::Ed25519 as i32,
+            "signature",
+            Instant::now(),
+        );
+        observe_request(Rpc::Keygen, -1, "error", Instant::now());
+
+        let text = String::from_utf8(encode().unwrap()).unwrap();
+        assert!(text.contains(
+            r#"tofnd_requests_total{algorithm="ALGORITHM_ED25519",outcome="signature",rpc="sign"}"#
+        ));
+        assert!(text
+            .contains(r#"tofnd_requests_total{algorithm="INVALID",outcome="error",rpc="keygen"}"#));
+        assert!(text.contains("tofnd_request_duration_seconds_bucket"));
+    }
+}
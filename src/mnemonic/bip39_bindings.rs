@@ -1,44 +1,64 @@
 //! This module provides wrappers for mnemonic creation, validation and seed
 //! extraction using the tiny-bip39 https://crates.io/crates/tiny-bip39 library.
 //!
-//! Default (and only) language is English. More languages to be added in the future.
+//! Phrases can be written in any language supported by tiny-bip39. As in BIP-39, seeds are derived
+//! from the phrase in the language of the mnemonic, so the same entropy has different keys in
+//! different languages. Mnemonics created before languages were supported are English.
 //!
 //! Zeroization:
 //!   All functions that accept and/or return structs that implement zeroization:
 //!   [crate::gg20::Password], [crate::gg20::Entropy], [bip39::Mnemonic], [bip39::Seed]
 
 use super::results::bip39::{Bip39Error::*, Bip39Result};
-use super::types::{Entropy, Password};
-use bip39::{Language, Mnemonic, Seed};
+use super::types::{Entropy, Language, Password};
+use bip39::{Mnemonic, MnemonicType, Seed};
 
-// TODO: we can enrich the API so that users can decide which language they want to use
-const DEFAUT_LANG: Language = Language::English;
+/// tiny-bip39 wordlist of a [Language]
+fn wordlist(language: Language) -> bip39::Language {
+    match language {
+        Language::English => bip39::Language::English,
+        Language::ChineseSimplified => bip39::Language::ChineseSimplified,
+        Language::ChineseTraditional => bip39::Language::ChineseTraditional,
+        Language::French => bip39::Language::French,
+        Language::Italian => bip39::Language::Italian,
+        Language::Japanese => bip39::Language::Japanese,
+        Language::Korean => bip39::Language::Korean,
+        Language::Spanish => bip39::Language::Spanish,
+    }
+}
 
-/// create a new 24 word mnemonic
-pub(super) fn bip39_new_w24() -> Entropy {
-    let mnemonic = Mnemonic::new(bip39::MnemonicType::Words24, DEFAUT_LANG);
-    Entropy(mnemonic.entropy().to_owned())
+/// create a new mnemonic of `word_count` words
+pub(super) fn bip39_new(word_count: usize) -> Bip39Result<Entropy> {
+    let mnemonic_type = MnemonicType::for_word_count(word_count).map_err(|_| WordCount)?;
+    // the entropy doesn't depend on the language
+    let mnemonic = Mnemonic::new(mnemonic_type, wordlist(Language::default()));
+    Ok(Entropy(mnemonic.entropy().to_owned()))
 }
 
 /// create a [Mnemonic] from [Entropy]; takes ownership of entropy and zeroizes it before exit
-pub(super) fn bip39_from_entropy(entropy: Entropy) -> Bip39Result<Mnemonic> {
+pub(super) fn bip39_from_entropy(entropy: Entropy, language: Language) -> Bip39Result<Mnemonic> {
     // try to get mnemonic from entropy
-    Mnemonic::from_entropy(&entropy.0, DEFAUT_LANG).map_err(|_| FromEntropy)
+    Mnemonic::from_entropy(&entropy.0, wordlist(language)).map_err(|_| FromEntropy)
 }
 
 /// create an [Entropy] from [Mnemonic]; takes ownership of phrase and zeroizes it before exit
-pub(super) fn bip39_from_phrase(phrase: Password) -> Bip39Result<Entropy> {
+pub(super) fn bip39_from_phrase(phrase: Password, language: Language) -> Bip39Result<Entropy> {
     // matching feels better than map_err() here
-    match Mnemonic::from_phrase(&phrase.0, DEFAUT_LANG) {
+    match Mnemonic::from_phrase(&phrase.0, wordlist(language)) {
         Ok(mnemonic) => Ok(Entropy(mnemonic.entropy().to_owned())),
         Err(_) => Err(FromPhrase),
     }
 }
 
-/// extract [Seed] from [Mnemonic]; takes ownership of entropy and password and zeroizes them before exit
-pub(super) fn bip39_seed(entropy: Entropy, password: Password) -> Bip39Result<Seed> {
+/// extract [Seed] from the [Mnemonic] of `entropy` in `language`; takes ownership of entropy and
+/// password and zeroizes them before exit
+pub(super) fn bip39_seed(
+    entropy: Entropy,
+    language: Language,
+    password: Password,
+) -> Bip39Result<Seed> {
     // matching feels better than map_err() here
-    match bip39_from_entropy(entropy) {
+    match bip39_from_entropy(entropy, language) {
         Ok(mnemonic) => Ok(Seed::new(&mnemonic, &password.0)),
         Err(_) => Err(FromEntropy),
     }
@@ -51,9 +71,14 @@ pub mod tests {
     use tracing::info;
     use tracing_test::traced_test;
 
+    /// create a new 24 word mnemonic
+    pub fn bip39_new_w24() -> Entropy {
+        bip39_new(24).unwrap()
+    }
+
     /// create a mnemonic from entropy; takes ownership of entropy and zeroizes it after
     pub fn bip39_to_phrase(entropy: Entropy) -> Bip39Result<Password> {
-        match Mnemonic::from_entropy(&entropy.0, DEFAUT_LANG) {
+        match Mnemonic::from_entropy(&entropy.0, wordlist(Language::English)) {
             Ok(mnemonic) => Ok(Password(mnemonic.phrase().to_owned())),
             Err(_) => Err(FromEntropy),
         }
@@ -63,7 +88,7 @@ pub mod tests {
     #[test]
     fn create() {
         let entropy = bip39_new_w24();
-        let mnemonic = Mnemonic::from_entropy(&entropy.0, DEFAUT_LANG).unwrap();
+        let mnemonic = Mnemonic::from_entropy(&entropy.0, wordlist(Language::English)).unwrap();
         let passphrase = mnemonic.phrase();
         info!(
             "created passphrase [{}] from entropy [{:?}]",
@@ -77,8 +102,36 @@ pub mod tests {
         let ok_entropy = Entropy(vec![42; 16]);
         let err_entropy = Entropy(vec![42; 15]);
 
-        assert!(bip39_from_entropy(ok_entropy).is_ok());
-        assert!(bip39_from_entropy(err_entropy).is_err());
+        assert!(bip39_from_entropy(ok_entropy, Language::English).is_ok());
+        assert!(bip39_from_entropy(err_entropy, Language::English).is_err());
+    }
+
+    #[test]
+    fn word_count() {
+        for word_count in [12, 15, 18, 21, 24] {
+            let entropy = bip39_new(word_count).unwrap();
+            // 32 bits of entropy per 3 words
+            assert_eq!(entropy.0.len() * 3, word_count * 4);
+        }
+        assert!(bip39_new(13).is_err());
+    }
+
+    #[test]
+    fn language_round_trip() {
+        let entropy = bip39_new(12).unwrap();
+        let english = bip39_from_entropy(entropy.clone(), Language::English).unwrap();
+        let japanese = bip39_from_entropy(entropy.clone(), Language::Japanese).unwrap();
+        assert_ne!(english.phrase(), japanese.phrase());
+
+        let phrase = Password(japanese.phrase().to_owned());
+        assert_eq!(
+            bip39_from_phrase(phrase.clone(), Language::Japanese)
+                .unwrap()
+                .0,
+            entropy.0
+        );
+        // phrases are only valid in their own language
+        assert!(bip39_from_phrase(phrase, Language::English).is_err());
     }
 
     #[traced_test]
@@ -90,8 +143,14 @@ pub mod tests {
             0x6A, 0x79,
         ];
 
-        let output =
-            hex::encode(bip39_seed(Entropy(entropy), Password("password".to_owned())).unwrap());
+        let output = hex::encode(
+            bip39_seed(
+                Entropy(entropy),
+                Language::English,
+                Password("password".to_owned()),
+            )
+            .unwrap(),
+        );
 
         goldie::assert_json!(output);
     }
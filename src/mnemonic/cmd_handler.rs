@@ -1,21 +1,35 @@
 // TODO: consider moving cmd_handler in KvManager
 
 use super::{
-    bip39_bindings::{bip39_from_phrase, bip39_new_w24, bip39_seed},
-    results::mnemonic::{
-        InnerMnemonicError::*, InnerMnemonicResult, MnemonicError::*, MnemonicResult, SeedResult,
+    bip39_bindings::{bip39_from_phrase, bip39_new, bip39_seed},
+    file_io::{FileIo, ImportContents},
+    results::{
+        file_io::FileIoError::ExportDisabled,
+        mnemonic::{
+            InnerMnemonicError::*, InnerMnemonicResult, MnemonicError::*, MnemonicResult,
+            SeedResult,
+        },
     },
-    types::{Entropy, Password},
+    slip39_bindings::{slip39_combine, slip39_split},
+    types::{Entropy, Language, Password, StoredMnemonic},
 };
-use crate::kv_manager::{
-    error::{InnerKvError, KvError},
-    KeyReservation, KvManager,
+use crate::{
+    kv_manager::{
+        error::{InnerKvError, KvError},
+        KeyReservation, KvManager,
+    },
+    metrics::MNEMONIC_COUNT,
 };
 use tofn::sdk::api::{deserialize, serialize, SecretRecoveryKey};
 
 use rpassword::read_password;
-use std::convert::TryInto;
-use tracing::{error, info};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryInto,
+    path::{Path, PathBuf},
+};
+use subtle::ConstantTimeEq;
+use tracing::{error, info, warn};
 
 // default key to store mnemonic
 const MNEMONIC_KEY: &str = "mnemonic";
@@ -28,63 +42,208 @@ const MNEMONIC_COUNT_KEY: &str = "mnemonic_count";
 // https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki#from-mnemonic-to-seed
 const MNEMONIC_PASSWORD: &str = "";
 
+// domain separation of mnemonic fingerprints
+const FINGERPRINT_DOMAIN: &[u8] = b"tofnd mnemonic fingerprint";
+
+/// File to import a mnemonic from, instead of standard input
+#[derive(Clone, Debug)]
+pub struct ImportFile {
+    pub path: PathBuf,
+    /// overwrite and delete the file after a successful import
+    pub delete: bool,
+}
+
+// maximum number of shares of a SLIP-39 group
+const MAX_SHARE_COUNT: u8 = 16;
+
+/// Split of the mnemonic into `count` SLIP-39 shares, any `threshold` of which recover it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShareSplit {
+    threshold: u8,
+    count: u8,
+}
+
+impl ShareSplit {
+    pub fn new(threshold: u8, count: u8) -> MnemonicResult<Self> {
+        if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+            return Err(WrongShareSplit(format!(
+                "threshold {} of {} shares; expected 1 <= threshold <= shares <= {}",
+                threshold, count, MAX_SHARE_COUNT
+            )));
+        }
+        // SLIP-39 doesn't allow copies of the secret
+        if threshold == 1 && count > 1 {
+            return Err(WrongShareSplit(format!(
+                "threshold 1 of {} shares would copy the mnemonic into every share",
+                count
+            )));
+        }
+        Ok(Self { threshold, count })
+    }
+}
+
+/// 2 of 3 shares
+impl Default for ShareSplit {
+    fn default() -> Self {
+        Self {
+            threshold: 2,
+            count: 3,
+        }
+    }
+}
+
+/// Word count and language of the phrase of a new mnemonic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhraseFormat {
+    word_count: usize,
+    language: Language,
+}
+
+impl PhraseFormat {
+    /// Word counts of bip39 phrases
+    pub const WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+
+    pub fn new(word_count: usize, language: Language) -> MnemonicResult<Self> {
+        if !Self::WORD_COUNTS.contains(&word_count) {
+            return Err(WrongWordCount(word_count));
+        }
+        Ok(Self {
+            word_count,
+            language,
+        })
+    }
+}
+
+/// 24 English words
+impl Default for PhraseFormat {
+    fn default() -> Self {
+        Self {
+            word_count: 24,
+            language: Language::default(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Cmd {
     Existing,
-    Create,
-    Import,
-    Export,
+    Create(PhraseFormat),
+    /// import a phrase written in the given language
+    Import(Option<ImportFile>, Language),
+    /// export in the language the mnemonic was stored with, which must match the given one
+    Export(Option<Language>),
     Rotate,
+    ExportShares(ShareSplit),
+    /// import from these files, or from standard input if empty, for a phrase in the given
+    /// language
+    ImportShares(Vec<ImportFile>, Language),
+    /// verify a phrase written in the given language
+    Verify(Option<ImportFile>, Language),
 }
 
 impl Cmd {
     pub fn from_string(cmd_str: &str) -> MnemonicResult<Self> {
         let cmd = match cmd_str {
             "existing" => Self::Existing,
-            "create" => Self::Create,
-            "import" => Self::Import,
-            "export" => Self::Export,
+            "create" => Self::Create(PhraseFormat::default()),
+            "import" => Self::Import(None, Language::default()),
+            "export" => Self::Export(None),
             "rotate" => Self::Rotate,
+            "export-shares" => Self::ExportShares(ShareSplit::default()),
+            "import-shares" => Self::ImportShares(vec![], Language::default()),
+            "verify" => Self::Verify(None, Language::default()),
             _ => return Err(WrongCommand(cmd_str.to_string())),
         };
         Ok(cmd)
     }
     /// On [Cmd::Existing], continue tofnd.
-    /// On [Cmd::Create], [Cmd::Import] or [Cmd::Export], exit tofnd.
+    /// On any other command, exit tofnd.
     pub fn exit_after_cmd(&self) -> bool {
         match &self {
             Cmd::Existing => false,
-            Cmd::Create => true,
-            Cmd::Import => true,
-            Cmd::Export => true,
+            Cmd::Create(_) => true,
+            Cmd::Import(..) => true,
+            Cmd::Export(_) => true,
             Cmd::Rotate => true,
+            Cmd::ExportShares(_) => true,
+            Cmd::ImportShares(..) => true,
+            Cmd::Verify(..) => true,
         }
     }
+
+    /// Whether the command writes the mnemonic to the export file or to shares
+    pub fn writes_export(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Create(_) | Cmd::Export(_) | Cmd::Rotate | Cmd::ExportShares(_)
+        )
+    }
 }
 
 /// implement mnemonic-specific functions for KvManager
 impl KvManager {
-    /// get mnemonic seed from kv-store
-    pub async fn seed(&self) -> SeedResult<SecretRecoveryKey> {
-        self.get_seed(MNEMONIC_KEY).await
+    /// get the entropy of the current mnemonic from kv-store
+    pub async fn entropy(&self) -> SeedResult<Entropy> {
+        self.get_entropy(MNEMONIC_KEY).await
     }
 
-    /// Get mnemonic seed under key
-    pub async fn get_seed(&self, key: &str) -> SeedResult<SecretRecoveryKey> {
-        let mnemonic = self
+    /// get the entropy and the language of the current mnemonic from kv-store
+    pub async fn mnemonic(&self) -> SeedResult<StoredMnemonic> {
+        self.get_mnemonic(MNEMONIC_KEY).await
+    }
+
+    /// Get the entropy of the mnemonic under key
+    pub async fn get_entropy(&self, key: &str) -> SeedResult<Entropy> {
+        Ok(self.get_mnemonic(key).await?.entropy)
+    }
+
+    /// Get the entropy and the language of the mnemonic under key
+    pub async fn get_mnemonic(&self, key: &str) -> SeedResult<StoredMnemonic> {
+        Ok(self
             .kv()
             .get(key)
             .await?
             .try_into()
-            .map_err(KvError::GetErr)?;
+            .map_err(KvError::GetErr)?)
+    }
 
+    /// Get mnemonic seed under key
+    pub async fn get_seed(&self, key: &str) -> SeedResult<SecretRecoveryKey> {
+        Self::mnemonic_to_seed(self.get_mnemonic(key).await?)
+    }
+
+    /// Derive the seed of a mnemonic from its phrase in the language it was stored with.
+    /// This is CPU-intensive, so async callers should run it outside of the async runtime.
+    pub fn mnemonic_to_seed(mnemonic: StoredMnemonic) -> SeedResult<SecretRecoveryKey> {
+        let StoredMnemonic { entropy, language } = mnemonic;
         Ok(
-            bip39_seed(mnemonic, Password(MNEMONIC_PASSWORD.to_owned()))?
+            bip39_seed(entropy, language, Password(MNEMONIC_PASSWORD.to_owned()))?
                 .as_bytes()
                 .try_into()?,
         )
     }
 
+    /// Fingerprint of the current mnemonic
+    pub async fn current_seed_fingerprint(&self) -> SeedResult<[u8; 32]> {
+        self.seed_fingerprint(MNEMONIC_KEY).await
+    }
+
+    /// Fingerprint of the mnemonic stored under `key`.
+    /// Rotation moves mnemonics to other keys, but their fingerprints stay the same.
+    pub async fn seed_fingerprint(&self, key: &str) -> SeedResult<[u8; 32]> {
+        let mnemonic = self.get_mnemonic(key).await?;
+
+        let mut hasher = Sha256::new()
+            .chain_update(FINGERPRINT_DOMAIN)
+            .chain_update(&mnemonic.entropy.0);
+        // the same entropy in another language derives another seed.
+        // English is left out, so that fingerprints of English mnemonics don't change
+        if mnemonic.language != Language::English {
+            hasher.update([mnemonic.language as u8]);
+        }
+        Ok(hasher.finalize().into())
+    }
+
     pub async fn seed_key_iter(&self) -> InnerMnemonicResult<Vec<String>> {
         let count = self.seed_count().await?;
         if count == 0 {
@@ -108,13 +267,31 @@ impl KvManager {
 
     /// async function that handles all mnemonic commands
     pub async fn handle_mnemonic(self, cmd: &Cmd) -> MnemonicResult<Self> {
+        // don't let concurrent requests observe a half-rotated mnemonic
+        let guard = self.mnemonic_lock().write().await;
         match cmd {
             Cmd::Existing => self.handle_existing().await.map_err(ExistingErr)?,
-            Cmd::Create => self.handle_create().await.map_err(CreateErr)?,
-            Cmd::Import => self.handle_import().await.map_err(ImportErr)?,
-            Cmd::Export => self.handle_export().await.map_err(ExportErr)?,
+            Cmd::Create(format) => self.handle_create(*format).await.map_err(CreateErr)?,
+            Cmd::Import(import_file, language) => self
+                .handle_import(import_file.as_ref(), *language)
+                .await
+                .map_err(ImportErr)?,
+            Cmd::Export(language) => self.handle_export(*language).await.map_err(ExportErr)?,
             Cmd::Rotate => self.handle_rotate().await.map_err(RotateErr)?,
+            Cmd::ExportShares(split) => self
+                .handle_export_shares(*split)
+                .await
+                .map_err(ExportSharesErr)?,
+            Cmd::ImportShares(import_files, language) => self
+                .handle_import_shares(import_files, *language)
+                .await
+                .map_err(ImportSharesErr)?,
+            Cmd::Verify(import_file, language) => self
+                .handle_verify(import_file.as_ref(), *language)
+                .await
+                .map_err(VerifyErr)?,
         };
+        drop(guard);
         Ok(self)
     }
 
@@ -128,7 +305,10 @@ impl KvManager {
 
         // try to get mnemonic from kv-store
         match self.kv().exists(MNEMONIC_KEY).await? {
-            true => Ok(()),
+            true => {
+                MNEMONIC_COUNT.set(self.seed_count().await?.into());
+                Ok(())
+            }
             false => Err(KvErr(KvError::ExistsErr(InnerKvError::LogicalErr(
                 "Mnemonic not found".to_string(),
             )))),
@@ -164,16 +344,16 @@ impl KvManager {
         Ok((key, count))
     }
 
-    /// inserts entropy to the kv-store
-    /// takes ownership of entropy to delegate zeroization.
-    async fn put_entropy(
+    /// inserts mnemonic to the kv-store
+    /// takes ownership of mnemonic to delegate zeroization.
+    async fn put_mnemonic(
         &self,
         reservation: KeyReservation,
-        entropy: Entropy,
+        mnemonic: StoredMnemonic,
     ) -> InnerMnemonicResult<()> {
         match self
             .kv()
-            .put(reservation, entropy.try_into().map_err(KvError::PutErr)?)
+            .put(reservation, mnemonic.try_into().map_err(KvError::PutErr)?)
             .await
         {
             // if put is ok, write the phrase to a file
@@ -189,9 +369,9 @@ impl KvManager {
         }
     }
 
-    /// inserts entropy to the kv-store
-    /// takes ownership of entropy to delegate zeroization.
-    async fn handle_insert(&self, entropy: Entropy) -> InnerMnemonicResult<()> {
+    /// inserts mnemonic to the kv-store
+    /// takes ownership of mnemonic to delegate zeroization.
+    async fn handle_insert(&self, mnemonic: StoredMnemonic) -> InnerMnemonicResult<()> {
         let (key, count) = self.get_next_key().await?;
 
         info!(
@@ -205,7 +385,7 @@ impl KvManager {
         })?;
 
         // Insert before updating the count to minimize state corruption if it fails in the middle
-        self.put_entropy(reservation, entropy).await?;
+        self.put_mnemonic(reservation, mnemonic).await?;
 
         // If delete isn't successful, the previous mnemonic count will still allow tofnd to work
         self.kv().delete(MNEMONIC_COUNT_KEY).await.map_err(|err| {
@@ -232,14 +412,21 @@ impl KvManager {
             .map_err(|err| {
                 error!("Could not update the mnemonic count in kv store: {:?}", err);
                 KvErr(err)
-            })
+            })?;
+
+        MNEMONIC_COUNT.set((count + 1).into());
+        Ok(())
     }
 
-    /// Creates a new entropy, inserts the entropy in the kv-store and exports it to a file
+    /// Creates a new entropy, inserts the entropy in the kv-store and exports it to a file in
+    /// the phrase `format`.
     /// If a mnemonic already exists in the kv store or an exported file already exists in
     /// the default path, an error is produced
-    async fn handle_create(&self) -> InnerMnemonicResult<()> {
-        info!("Creating mnemonic");
+    async fn handle_create(&self, format: PhraseFormat) -> InnerMnemonicResult<()> {
+        info!(
+            "Creating {} word mnemonic in {:?}",
+            format.word_count, format.language
+        );
 
         if self.kv().exists(MNEMONIC_KEY).await? {
             error!("Mnemonic was already created");
@@ -248,63 +435,193 @@ impl KvManager {
             ))));
         }
 
-        // create a new entropy
-        let new_entropy = bip39_new_w24();
+        // fail before inserting, so that the new mnemonic is not stored without a backup
+        if !self.io().can_export() {
+            return Err(FileIoErr(ExportDisabled));
+        }
 
-        self.handle_insert(new_entropy.clone()).await?;
+        // create a new entropy
+        let new_entropy = bip39_new(format.word_count)?;
+
+        self.handle_insert(StoredMnemonic {
+            entropy: new_entropy.clone(),
+            language: format.language,
+        })
+        .await?;
+
+        // no key of the new mnemonic was generated before keygen records existed.
+        // If the marker isn't written, key presence falls back to deriving keys without a record
+        if let Err(err) = self.mark_keygen_records_complete().await {
+            warn!("could not mark keygen records complete: {:?}", err);
+        }
 
-        Ok(self.io().entropy_to_file(new_entropy)?)
+        Ok(self.io().entropy_to_file(new_entropy, format.language)?)
     }
 
-    /// Inserts a new mnemonic to the kv-store.
+    /// Inserts a new mnemonic, read from `import_file` or from standard input in `language`, to
+    /// the kv-store.
     /// If a mnemonic already exists in the kv store, a new entry is created
     /// storing it as a rotated out mnemonic.
-    async fn handle_import(&self) -> InnerMnemonicResult<()> {
+    async fn handle_import(
+        &self,
+        import_file: Option<&ImportFile>,
+        language: Language,
+    ) -> InnerMnemonicResult<()> {
         info!("Importing mnemonic");
-        let imported_phrase = Password(read_password().map_err(|e| PasswordErr(e.to_string()))?);
-        let imported_entropy = bip39_from_phrase(imported_phrase)?;
-        self.handle_insert(imported_entropy).await
+        let imported_phrase = match import_file {
+            Some(import_file) => read_import_file(&import_file.path, &mut None)?,
+            None => Password(read_password().map_err(|e| PasswordErr(e.to_string()))?),
+        };
+        let imported_entropy = bip39_from_phrase(imported_phrase, language)?;
+        self.handle_insert(StoredMnemonic {
+            entropy: imported_entropy,
+            language,
+        })
+        .await?;
+
+        if let Some(ImportFile { path, delete: true }) = import_file {
+            FileIo::remove_file(path)?;
+            info!("Import file {:?} overwritten and deleted", path);
+        }
+        Ok(())
+    }
+
+    /// Inserts a new mnemonic, combined from SLIP-39 shares, to the kv-store.
+    /// Shares are read from `import_files`, or from standard input if there are none.
+    /// Shares hold the entropy only, so `language` must be the language the mnemonic was
+    /// created in, as the seed is derived from the phrase in that language.
+    async fn handle_import_shares(
+        &self,
+        import_files: &[ImportFile],
+        language: Language,
+    ) -> InnerMnemonicResult<()> {
+        info!("Importing mnemonic shares");
+        let shares = match import_files.is_empty() {
+            true => read_shares()?,
+            false => {
+                let mut export_passphrase = None;
+                import_files
+                    .iter()
+                    .map(|import_file| read_import_file(&import_file.path, &mut export_passphrase))
+                    .collect::<InnerMnemonicResult<_>>()?
+            }
+        };
+        let imported_entropy = slip39_combine(shares)?;
+        self.handle_insert(StoredMnemonic {
+            entropy: imported_entropy,
+            language,
+        })
+        .await?;
+
+        for import_file in import_files.iter().filter(|import_file| import_file.delete) {
+            FileIo::remove_file(&import_file.path)?;
+            info!("Import file {:?} overwritten and deleted", import_file.path);
+        }
+        Ok(())
+    }
+
+    /// Exports the current mnemonic to a directory of SLIP-39 shares
+    async fn handle_export_shares(&self, split: ShareSplit) -> InnerMnemonicResult<()> {
+        info!(
+            "Exporting mnemonic in {} shares with threshold {}",
+            split.count, split.threshold
+        );
+
+        // fail before splitting the mnemonic
+        self.io().check_if_not_exported()?;
+
+        let shares = slip39_split(self.entropy().await?, split.threshold, split.count)?;
+        Ok(self.io().shares_to_files(shares)?)
+    }
+
+    /// Checks a phrase, read from `import_file` or from standard input in `language`, against the
+    /// current and rotated out mnemonics, without writing the mnemonic anywhere.
+    /// Fails if the phrase doesn't match any of them.
+    async fn handle_verify(
+        &self,
+        import_file: Option<&ImportFile>,
+        language: Language,
+    ) -> InnerMnemonicResult<()> {
+        info!("Verifying mnemonic");
+        let phrase = match import_file {
+            Some(import_file) => read_import_file(&import_file.path, &mut None)?,
+            None => Password(read_password().map_err(|e| PasswordErr(e.to_string()))?),
+        };
+        let entropy = bip39_from_phrase(phrase, language)?;
+
+        let (index, key) = self
+            .find_mnemonic(&entropy, language)
+            .await?
+            .ok_or(NoMatchingMnemonic)?;
+        info!(
+            "Phrase matches mnemonic {} under key '{}'; 0 is the current mnemonic",
+            index, key
+        );
+        Ok(())
     }
 
-    /// Exports the current mnemonic to a file
-    async fn handle_export(&self) -> InnerMnemonicResult<()> {
+    /// Index in [KvManager::seed_key_iter] and key of the stored mnemonic with `entropy` and
+    /// `language`, if any
+    async fn find_mnemonic(
+        &self,
+        entropy: &Entropy,
+        language: Language,
+    ) -> InnerMnemonicResult<Option<(usize, String)>> {
+        let mut found = None;
+        // compare every mnemonic in constant time, so that timing doesn't reveal the stored entropy
+        for (index, key) in self.seed_key_iter().await?.into_iter().enumerate() {
+            let stored = self.get_mnemonic(&key).await?;
+            if bool::from(stored.entropy.0.ct_eq(&entropy.0))
+                && stored.language == language
+                && found.is_none()
+            {
+                found = Some((index, key));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Exports the current mnemonic to a file, in the language it was stored with.
+    /// The seed is derived from the phrase in that language, so other languages are refused.
+    async fn handle_export(&self, language: Option<Language>) -> InnerMnemonicResult<()> {
         info!("Exporting mnemonic");
 
         // try to get mnemonic from kv-store
-        let entropy = self
-            .kv()
-            .get(MNEMONIC_KEY)
-            .await
-            .map_err(|err| {
-                error!("Did not find mnemonic in kv store {:?}", err);
-                err
-            })?
-            .try_into()
-            .map_err(KvError::GetErr)?;
+        let mnemonic = self.get_mnemonic(MNEMONIC_KEY).await.map_err(|err| {
+            error!("Did not find mnemonic in kv store {:?}", err);
+            err
+        })?;
+
+        if let Some(language) = language.filter(|language| *language != mnemonic.language) {
+            error!(
+                "Mnemonic was stored in {:?}; a phrase in {:?} would derive other keys",
+                mnemonic.language, language
+            );
+            return Err(WrongLanguage(mnemonic.language));
+        }
 
         // write to file
         info!("Mnemonic found in kv store");
-        Ok(self.io().entropy_to_file(entropy)?)
+        Ok(self
+            .io()
+            .entropy_to_file(mnemonic.entropy, mnemonic.language)?)
     }
 
     /// Rotates out existing mnemonic for new one in the kv-store and exports it to a file
+    /// The new mnemonic keeps the word count and language of the existing one.
     /// If an exported file already exists in the default path, an error is produced
     async fn handle_rotate(&self) -> InnerMnemonicResult<()> {
         info!("Rotating mnemonic");
-        // create a new entropy
-        let new_entropy = bip39_new_w24();
+        let current_mnemonic = self.get_mnemonic(MNEMONIC_KEY).await?;
+        let language = current_mnemonic.language;
 
-        // export right away in case of intermediate failures
-        self.io().entropy_to_file(new_entropy.clone())?;
+        // create a new entropy; every 4 bytes of entropy are written in 3 words
+        let new_entropy = bip39_new(current_mnemonic.entropy.0.len() * 3 / 4)?;
 
-        let current_entropy: Entropy = self
-            .kv()
-            .get(MNEMONIC_KEY)
-            .await?
-            .try_into()
-            .map_err(KvError::GetErr)?;
+        // export right away in case of intermediate failures
+        self.io().entropy_to_file(new_entropy.clone(), language)?;
 
-        self.handle_insert(current_entropy.clone()).await?;
+        self.handle_insert(current_mnemonic).await?;
 
         info!("reserving mnemonic");
 
@@ -322,12 +639,59 @@ impl KvManager {
                 KvErr(err)
             })?;
 
-        self.put_entropy(reservation, new_entropy).await?;
+        self.put_mnemonic(
+            reservation,
+            StoredMnemonic {
+                entropy: new_entropy,
+                language,
+            },
+        )
+        .await?;
 
         Ok(())
     }
 }
 
+/// Read a phrase from `path`, decrypting it if it holds an encrypted export. The export passphrase
+/// is prompted for the first encrypted file, and kept in `export_passphrase` for the next ones.
+fn read_import_file(
+    path: &Path,
+    export_passphrase: &mut Option<Password>,
+) -> InnerMnemonicResult<Password> {
+    let export = match FileIo::read_import_file(path)? {
+        ImportContents::Phrase(phrase) => return Ok(phrase),
+        ImportContents::Encrypted(export) => export,
+    };
+
+    let passphrase = match export_passphrase.take() {
+        Some(passphrase) => passphrase,
+        None => {
+            println!("Please type your export passphrase:");
+            Password(read_password().map_err(|e| PasswordErr(e.to_string()))?)
+        }
+    };
+    let phrase = export.decrypt(&passphrase);
+    *export_passphrase = Some(passphrase);
+
+    Ok(phrase?)
+}
+
+/// Read SLIP-39 shares from standard input, one per line, until an empty line
+fn read_shares() -> InnerMnemonicResult<Vec<Password>> {
+    let mut shares = vec![];
+    loop {
+        println!(
+            "Please type share {}, or an empty line after the last share:",
+            shares.len() + 1
+        );
+        let share = Password(read_password().map_err(|e| PasswordErr(e.to_string()))?);
+        if share.0.trim().is_empty() {
+            return Ok(shares);
+        }
+        shares.push(share);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{io::Read, path::PathBuf};
@@ -339,7 +703,14 @@ mod tests {
             error::{InnerKvError, KvError},
             KvManager,
         },
-        mnemonic::results::{file_io::FileIoError, mnemonic::InnerMnemonicError},
+        mnemonic::{
+            bip39_bindings::{
+                bip39_from_entropy,
+                tests::{bip39_new_w24, bip39_to_phrase},
+            },
+            file_io::ExportMode,
+            results::{file_io::FileIoError, mnemonic::InnerMnemonicError},
+        },
     };
 
     use super::*;
@@ -348,7 +719,9 @@ mod tests {
     // create a service
     fn get_kv_manager(testdir: PathBuf) -> KvManager {
         // create test dirs
-        KvManager::new(testdir, get_test_password()).unwrap()
+        KvManager::new(testdir, get_test_password())
+            .unwrap()
+            .with_export_mode(Some(ExportMode::Plaintext))
     }
 
     #[traced_test]
@@ -357,11 +730,18 @@ mod tests {
         let testdir = testdir!();
         // create a service
         let kv = get_kv_manager(testdir);
+        // without an export mode, no mnemonic is created
+        let no_export = kv.clone().with_export_mode(None);
+        assert!(matches!(
+            no_export.handle_create(PhraseFormat::default()).await,
+            Err(InnerMnemonicError::FileIoErr(FileIoError::ExportDisabled))
+        ));
+        assert_eq!(kv.seed_count().await.unwrap(), 0);
         // first attempt should succeed
-        assert!(kv.handle_create().await.is_ok());
+        assert!(kv.handle_create(PhraseFormat::default()).await.is_ok());
         // second attempt should fail
         assert!(matches!(
-            kv.handle_create().await,
+            kv.handle_create(PhraseFormat::default()).await,
             Err(InnerMnemonicError::KvErr(KvError::ReserveErr(
                 InnerKvError::LogicalErr(_)
             )))
@@ -374,10 +754,110 @@ mod tests {
         let testdir = testdir!();
         // create a service
         let kv = get_kv_manager(testdir.clone());
+        let mnemonic = || StoredMnemonic {
+            entropy: bip39_new_w24(),
+            language: Language::English,
+        };
         // insert should succeed
-        assert!(kv.handle_insert(bip39_new_w24()).await.is_ok());
+        assert!(kv.handle_insert(mnemonic()).await.is_ok());
         // insert should succeed again
-        assert!(kv.handle_insert(bip39_new_w24()).await.is_ok());
+        assert!(kv.handle_insert(mnemonic()).await.is_ok());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_import_file() {
+        let testdir = testdir!();
+        let kv = get_kv_manager(testdir.clone());
+
+        let entropy = bip39_new_w24();
+        let path = testdir.join("import");
+        std::fs::write(
+            &path,
+            bip39_to_phrase(entropy.clone()).unwrap().0.as_bytes(),
+        )
+        .unwrap();
+
+        // an invalid phrase is not imported, and its file is kept
+        let invalid_path = testdir.join("invalid");
+        std::fs::write(&invalid_path, "not a mnemonic").unwrap();
+        let import_file = ImportFile {
+            path: invalid_path.clone(),
+            delete: true,
+        };
+        assert!(kv
+            .handle_import(Some(&import_file), Language::English)
+            .await
+            .is_err());
+        assert!(invalid_path.exists());
+
+        let import_file = ImportFile {
+            path: path.clone(),
+            delete: true,
+        };
+        kv.handle_import(Some(&import_file), Language::English)
+            .await
+            .unwrap();
+        assert_eq!(kv.entropy().await.unwrap().0, entropy.0);
+        assert!(!path.exists());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_verify() {
+        let testdir = testdir!();
+        let kv = get_kv_manager(testdir.clone());
+        kv.handle_create(PhraseFormat::default()).await.unwrap();
+        let entropy = kv.entropy().await.unwrap();
+
+        // keep the backup out of the export path
+        let backup_path = testdir.join("backup");
+        std::fs::rename(kv.io().export_path(), &backup_path).unwrap();
+        let import_file = ImportFile {
+            path: backup_path.clone(),
+            delete: false,
+        };
+
+        kv.handle_verify(Some(&import_file), Language::English)
+            .await
+            .unwrap();
+        assert_eq!(
+            kv.find_mnemonic(&entropy, Language::English).await.unwrap(),
+            Some((0, MNEMONIC_KEY.to_string()))
+        );
+        // the same entropy in another language is another mnemonic
+        assert_eq!(
+            kv.find_mnemonic(&entropy, Language::French).await.unwrap(),
+            None
+        );
+
+        // the backup still matches once rotated out
+        for _ in 0..2 {
+            kv.handle_rotate().await.unwrap();
+            std::fs::remove_file(kv.io().export_path()).unwrap();
+        }
+        kv.handle_verify(Some(&import_file), Language::English)
+            .await
+            .unwrap();
+        assert_eq!(
+            kv.find_mnemonic(&entropy, Language::English).await.unwrap(),
+            Some((2, format!("{}_{}", MNEMONIC_KEY, 1)))
+        );
+
+        // other phrases don't match
+        std::fs::write(
+            &backup_path,
+            bip39_to_phrase(bip39_new_w24()).unwrap().0.as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            kv.handle_verify(Some(&import_file), Language::English)
+                .await,
+            Err(InnerMnemonicError::NoMatchingMnemonic)
+        ));
+
+        // nothing is written to disk
+        assert!(kv.io().check_if_not_exported().is_ok());
     }
 
     #[traced_test]
@@ -396,12 +876,12 @@ mod tests {
         // mnemonic should not be exported
         assert!(kv.io().check_if_not_exported().is_ok());
         // create a new mnemonic
-        assert!(kv.handle_create().await.is_ok());
+        assert!(kv.handle_create(PhraseFormat::default()).await.is_ok());
         // mnemonic should now be exported
         assert!(kv.io().check_if_not_exported().is_err());
         // export should fail because create also exports
         assert!(matches!(
-            kv.handle_export().await,
+            kv.handle_export(None).await,
             Err(InnerMnemonicError::FileIoErr(FileIoError::Exists(_)))
         ));
         // handle existing should fail because export file exists
@@ -418,7 +898,7 @@ mod tests {
         // create a service
         let kv = get_kv_manager(testdir.clone());
         // create a new mnemonic
-        assert!(kv.handle_create().await.is_ok());
+        assert!(kv.handle_create(PhraseFormat::default()).await.is_ok());
         // handle_existing should fail because export file exists
         assert!(matches!(
             kv.handle_existing().await,
@@ -426,11 +906,54 @@ mod tests {
         ));
         // export should fail because export file exists
         assert!(matches!(
-            kv.handle_export().await,
+            kv.handle_export(None).await,
             Err(InnerMnemonicError::FileIoErr(FileIoError::Exists(_)))
         ));
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn test_shares_round_trip() {
+        let testdir = testdir!();
+        let kv = get_kv_manager(testdir.join("original"));
+        kv.handle_create(PhraseFormat::default()).await.unwrap();
+        std::fs::remove_file(kv.io().export_path()).unwrap();
+        kv.handle_export_shares(ShareSplit::new(3, 5).unwrap())
+            .await
+            .unwrap();
+
+        let import_files = |indices: &[usize]| -> Vec<ImportFile> {
+            indices
+                .iter()
+                .map(|index| ImportFile {
+                    path: kv
+                        .io()
+                        .export_shares_path()
+                        .join(format!("share-{}", index)),
+                    delete: false,
+                })
+                .collect()
+        };
+
+        // fewer than 3 shares don't recover the mnemonic
+        let too_few = get_kv_manager(testdir.join("too-few"));
+        assert!(too_few
+            .handle_import_shares(&import_files(&[2, 4]), Language::English)
+            .await
+            .is_err());
+
+        // any 3 shares recover the same seed, and so the same keys
+        let recovered = get_kv_manager(testdir.join("recovered"));
+        recovered
+            .handle_import_shares(&import_files(&[5, 1, 3]), Language::English)
+            .await
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", recovered.get_seed(MNEMONIC_KEY).await.unwrap()),
+            format!("{:?}", kv.get_seed(MNEMONIC_KEY).await.unwrap())
+        );
+    }
+
     #[traced_test]
     #[tokio::test]
     async fn test_rotate() {
@@ -446,7 +969,7 @@ mod tests {
 
         for i in 0..rotations {
             if i == 0 {
-                assert!(kv.handle_create().await.is_ok());
+                assert!(kv.handle_create(PhraseFormat::default()).await.is_ok());
             } else {
                 assert!(kv.handle_rotate().await.is_ok());
             }
@@ -462,7 +985,8 @@ mod tests {
 
             seeds.push(
                 bip39_seed(
-                    bip39_from_phrase(Password(phrase)).unwrap(),
+                    bip39_from_phrase(Password(phrase), Language::English).unwrap(),
+                    Language::English,
                     Password(MNEMONIC_PASSWORD.to_owned()),
                 )
                 .unwrap()
@@ -495,4 +1019,131 @@ mod tests {
             );
         }
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_fingerprint_follows_rotation() {
+        let kv = get_kv_manager(testdir!());
+        kv.handle_create(PhraseFormat::default()).await.unwrap();
+        std::fs::remove_file(kv.io().export_path()).unwrap();
+        let fingerprint = kv.seed_fingerprint(MNEMONIC_KEY).await.unwrap();
+
+        kv.handle_rotate().await.unwrap();
+
+        // the rotated out mnemonic keeps its fingerprint under its new key
+        let rotated_key = format!("{}_{}", MNEMONIC_KEY, 1);
+        assert_eq!(
+            kv.seed_fingerprint(&rotated_key).await.unwrap(),
+            fingerprint
+        );
+        assert_ne!(
+            kv.seed_fingerprint(MNEMONIC_KEY).await.unwrap(),
+            fingerprint
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_language() {
+        let testdir = testdir!();
+        let kv = get_kv_manager(testdir.clone());
+        let format = PhraseFormat::new(12, Language::French).unwrap();
+        assert!(PhraseFormat::new(13, Language::French).is_err());
+
+        // create writes the phrase in the chosen word count and language
+        kv.handle_create(format).await.unwrap();
+        let phrase = std::fs::read_to_string(kv.io().export_path()).unwrap();
+        std::fs::remove_file(kv.io().export_path()).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 12);
+        let entropy = bip39_from_phrase(Password(phrase.clone()), Language::French).unwrap();
+        assert_eq!(kv.entropy().await.unwrap().0, entropy.0);
+
+        // export gives back the original phrase, and refuses other languages
+        kv.handle_export(None).await.unwrap();
+        let exported = std::fs::read_to_string(kv.io().export_path()).unwrap();
+        std::fs::remove_file(kv.io().export_path()).unwrap();
+        assert_eq!(exported, phrase);
+        assert!(matches!(
+            kv.handle_export(Some(Language::English)).await,
+            Err(InnerMnemonicError::WrongLanguage(Language::French))
+        ));
+        assert!(kv.io().check_if_not_exported().is_ok());
+
+        // the seed is the bip39 seed of the French phrase
+        let seed: SecretRecoveryKey = bip39_seed(
+            entropy.clone(),
+            Language::French,
+            Password(MNEMONIC_PASSWORD.to_owned()),
+        )
+        .unwrap()
+        .as_bytes()
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            format!("{:?}", kv.get_seed(MNEMONIC_KEY).await.unwrap()),
+            format!("{:?}", seed)
+        );
+
+        // the same entropy in English derives another seed
+        let english_kv = get_kv_manager(testdir.join("english"));
+        let import_path = testdir.join("import");
+        std::fs::write(&import_path, bip39_to_phrase(entropy).unwrap().0.as_bytes()).unwrap();
+        let import_file = ImportFile {
+            path: import_path.clone(),
+            delete: true,
+        };
+        english_kv
+            .handle_import(Some(&import_file), Language::English)
+            .await
+            .unwrap();
+        assert_ne!(
+            format!("{:?}", english_kv.get_seed(MNEMONIC_KEY).await.unwrap()),
+            format!("{:?}", seed)
+        );
+
+        // importing the French phrase in French recovers the seed
+        let french_kv = get_kv_manager(testdir.join("french"));
+        std::fs::write(&import_path, phrase).unwrap();
+        french_kv
+            .handle_import(Some(&import_file), Language::French)
+            .await
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", french_kv.get_seed(MNEMONIC_KEY).await.unwrap()),
+            format!("{:?}", seed)
+        );
+
+        // rotation keeps the word count and language
+        kv.handle_rotate().await.unwrap();
+        let phrase = std::fs::read_to_string(kv.io().export_path()).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 12);
+        assert!(bip39_from_phrase(Password(phrase), Language::French).is_ok());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_legacy_mnemonic() {
+        let kv = get_kv_manager(testdir!());
+
+        // mnemonics used to be stored as their entropy only
+        let entropy = bip39_new_w24();
+        let reservation = kv.kv().reserve_key(MNEMONIC_KEY.to_owned()).await.unwrap();
+        kv.kv()
+            .put(reservation, serialize(&entropy).unwrap())
+            .await
+            .unwrap();
+
+        let mnemonic = kv.get_mnemonic(MNEMONIC_KEY).await.unwrap();
+        assert_eq!(mnemonic.entropy.0, entropy.0);
+        assert_eq!(mnemonic.language, Language::English);
+
+        kv.handle_export(None).await.unwrap();
+        let phrase = std::fs::read_to_string(kv.io().export_path()).unwrap();
+        assert_eq!(
+            phrase,
+            bip39_from_entropy(entropy, Language::English)
+                .unwrap()
+                .phrase()
+        );
+    }
 }
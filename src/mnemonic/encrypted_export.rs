@@ -0,0 +1,208 @@
+//! Password-encrypted export file of a mnemonic.
+//!
+//! The phrase is encrypted with [XChaCha20Poly1305] under a key derived from an export passphrase
+//! with [scrypt], as in [crate::encrypted_sled]. The file is TOML, with binary fields hex-encoded:
+//!
+//! ```toml
+//! version = 1
+//! kdf = "scrypt"
+//! log-n = 15
+//! r = 8
+//! p = 1
+//! salt = "<32 bytes>"
+//! cipher = "xchacha20poly1305"
+//! nonce = "<24 bytes>"
+//! ciphertext = "<encrypted phrase followed by the 16-byte tag>"
+//! ```
+//!
+//! The scrypt parameters are stored in the file, so that files remain readable if the defaults
+//! change.
+
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use super::{
+    results::file_io::{FileIoError::*, FileIoResult},
+    types::Password,
+};
+
+const VERSION: u32 = 1;
+const KDF: &str = "scrypt";
+const CIPHER: &str = "xchacha20poly1305";
+
+/// scrypt parameters of new exports; the same as those of the kv store
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// largest scrypt parameters accepted from a file, so that a crafted file can't make key
+/// derivation take unbounded time and memory; `log_n = 20, r = 16` takes 2 GiB
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const MAX_SCRYPT_R: u32 = 16;
+const MAX_SCRYPT_P: u32 = 4;
+
+/// authenticated along with the phrase, so that the ciphertext can't be used in another context
+const ASSOCIATED_DATA: &[u8] = b"tofnd mnemonic export v1";
+
+/// Contents of an encrypted export file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EncryptedExport {
+    version: u32,
+    kdf: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    /// hex-encoded
+    salt: String,
+    cipher: String,
+    /// hex-encoded
+    nonce: String,
+    /// hex-encoded
+    ciphertext: String,
+}
+
+impl EncryptedExport {
+    /// Encrypt `phrase` under `passphrase`
+    pub fn encrypt(phrase: &Password, passphrase: &Password) -> FileIoResult<Self> {
+        let mut salt = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let mut nonce = XNonce::default();
+        rand::thread_rng().fill_bytes(nonce.as_mut_slice());
+
+        let cipher = cipher(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+        let mut ciphertext = phrase.0.as_bytes().to_vec();
+        cipher
+            .encrypt_in_place(&nonce, ASSOCIATED_DATA, &mut ciphertext)
+            .map_err(|err| Encryption(err.to_string()))?;
+
+        Ok(Self {
+            version: VERSION,
+            kdf: KDF.to_string(),
+            log_n: SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+            cipher: CIPHER.to_string(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypt the phrase with `passphrase`
+    pub fn decrypt(&self, passphrase: &Password) -> FileIoResult<Password> {
+        if self.version != VERSION || self.kdf != KDF || self.cipher != CIPHER {
+            return Err(InvalidExport(format!(
+                "unsupported export version {} with kdf {:?} and cipher {:?}",
+                self.version, self.kdf, self.cipher
+            )));
+        }
+        let salt = decode(&self.salt, "salt")?;
+        let nonce = decode(&self.nonce, "nonce")?;
+        if nonce.len() != XNonce::default().len() {
+            return Err(InvalidExport("invalid nonce length".to_string()));
+        }
+        let mut phrase = decode(&self.ciphertext, "ciphertext")?;
+
+        cipher(passphrase, &salt, self.log_n, self.r, self.p)?
+            .decrypt_in_place(XNonce::from_slice(&nonce), ASSOCIATED_DATA, &mut phrase)
+            .map_err(|_| Decryption)?;
+
+        String::from_utf8(phrase).map(Password).map_err(|err| {
+            let mut phrase = err.into_bytes();
+            phrase.zeroize();
+            InvalidExport("phrase is not valid UTF-8".to_string())
+        })
+    }
+
+    pub fn to_toml(&self) -> FileIoResult<String> {
+        toml::to_string(self).map_err(|err| InvalidExport(err.to_string()))
+    }
+
+    /// Parse an encrypted export file; returns [None] if `contents` is not one
+    pub fn from_toml(contents: &str) -> Option<Self> {
+        toml::from_str(contents).ok()
+    }
+}
+
+fn cipher(
+    passphrase: &Password,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> FileIoResult<XChaCha20Poly1305> {
+    if log_n > MAX_SCRYPT_LOG_N || r > MAX_SCRYPT_R || p > MAX_SCRYPT_P {
+        return Err(InvalidExport(format!(
+            "scrypt parameters log-n = {}, r = {}, p = {} exceed log-n = {}, r = {}, p = {}",
+            log_n, r, p, MAX_SCRYPT_LOG_N, MAX_SCRYPT_R, MAX_SCRYPT_P
+        )));
+    }
+    let params = scrypt::Params::new(log_n, r, p, 32)
+        .map_err(|err| InvalidExport(format!("invalid scrypt parameters: {}", err)))?;
+
+    let mut key = chacha20poly1305::Key::default();
+    scrypt::scrypt(passphrase.0.as_bytes(), salt, &params, key.as_mut_slice())
+        .map_err(|err| Encryption(err.to_string()))?;
+    let cipher = XChaCha20Poly1305::new(&key);
+    key.zeroize();
+
+    Ok(cipher)
+}
+
+fn decode(value: &str, field: &str) -> FileIoResult<Vec<u8>> {
+    hex::decode(value).map_err(|_| InvalidExport(format!("{} is not hex-encoded", field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let phrase = Password("abandon ability able".to_string());
+        let passphrase = Password("correct horse".to_string());
+
+        let export = EncryptedExport::encrypt(&phrase, &passphrase).unwrap();
+        let contents = export.to_toml().unwrap();
+        assert!(!contents.contains("abandon"));
+
+        let export = EncryptedExport::from_toml(&contents).unwrap();
+        assert_eq!(export.decrypt(&passphrase).unwrap().0, phrase.0);
+
+        assert!(matches!(
+            export.decrypt(&Password("wrong".to_string())),
+            Err(Decryption)
+        ));
+
+        // a plaintext phrase is not an encrypted export
+        assert!(EncryptedExport::from_toml(&phrase.0).is_none());
+    }
+
+    #[test]
+    fn test_scrypt_params_bounds() {
+        let phrase = Password("abandon ability able".to_string());
+        let passphrase = Password("correct horse".to_string());
+        let export = EncryptedExport::encrypt(&phrase, &passphrase).unwrap();
+
+        // rejected before any key is derived
+        for export in [
+            EncryptedExport {
+                log_n: 64,
+                ..export.clone()
+            },
+            EncryptedExport {
+                r: u32::MAX,
+                ..export.clone()
+            },
+            EncryptedExport { p: 5, ..export },
+        ] {
+            assert!(matches!(export.decrypt(&passphrase), Err(InvalidExport(_))));
+        }
+    }
+}
@@ -1,28 +1,94 @@
 //! This module handles file IO.
 
-use std::{io::Write, path::PathBuf};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
 
+use rpassword::read_password;
 use tracing::info;
 
-use super::types::Entropy;
-use super::{bip39_bindings::bip39_from_entropy, results::file_io::FileIoError::Exists};
+use super::types::{Entropy, Language, Password};
+use super::{
+    bip39_bindings::bip39_from_entropy,
+    encrypted_export::EncryptedExport,
+    results::file_io::FileIoError::{EmptyPassphrase, Exists, ExportDisabled, PassphraseMismatch},
+};
 
 /// name of export file
 const EXPORT_FILE: &str = "export";
+/// name of the directory of exported SLIP-39 shares
+const EXPORT_SHARES_DIR: &str = "export-shares";
+/// export files are only readable by the owner of the tofnd process
+const EXPORT_FILE_MODE: u32 = 0o600;
+const EXPORT_SHARES_DIR_MODE: u32 = 0o700;
 
 use super::results::file_io::FileIoResult;
 
+/// How the mnemonic is written to the export file
+#[derive(Clone)]
+pub enum ExportMode {
+    /// the phrase in plaintext; must be requested explicitly
+    Plaintext,
+    /// the phrase encrypted under an export passphrase, see [EncryptedExport]
+    Encrypted(Password),
+}
+
+impl ExportMode {
+    /// Read an export passphrase from standard input, twice to catch typos
+    pub fn prompt() -> FileIoResult<Self> {
+        println!("Please type your export passphrase:");
+        let passphrase = Password(read_password()?);
+        println!("Please type your export passphrase again:");
+        if passphrase.0 != Password(read_password()?).0 {
+            return Err(PassphraseMismatch);
+        }
+        Self::encrypted(passphrase.0.clone())
+    }
+
+    /// Encrypt exports under `passphrase`, which must not be empty
+    pub fn encrypted(passphrase: String) -> FileIoResult<Self> {
+        let passphrase = Password(passphrase);
+        if passphrase.0.is_empty() {
+            return Err(EmptyPassphrase);
+        }
+        Ok(Self::Encrypted(passphrase))
+    }
+}
+
+/// Contents of an import file
+pub(super) enum ImportContents {
+    Phrase(Password),
+    Encrypted(EncryptedExport),
+}
+
 /// FileIO wraps all IO functionality
 #[derive(Clone)]
 pub struct FileIo {
     export_path: PathBuf,
+    export_shares_path: PathBuf,
+    /// exports fail if [None]
+    export_mode: Option<ExportMode>,
 }
 
 impl FileIo {
-    /// FileIO constructor
-    pub fn new(mut export_path: PathBuf) -> FileIo {
-        export_path.push(EXPORT_FILE);
-        FileIo { export_path }
+    /// FileIO constructor; exports fail until an [ExportMode] is set
+    pub fn new(root: PathBuf) -> FileIo {
+        FileIo {
+            export_path: root.join(EXPORT_FILE),
+            export_shares_path: root.join(EXPORT_SHARES_DIR),
+            export_mode: None,
+        }
+    }
+
+    /// Write exports with `export_mode`
+    pub fn with_export_mode(self, export_mode: Option<ExportMode>) -> Self {
+        Self {
+            export_mode,
+            ..self
+        }
     }
 
     /// Get the path of export file
@@ -30,38 +96,121 @@ impl FileIo {
         &self.export_path
     }
 
+    /// Get the path of the directory of exported shares
+    pub fn export_shares_path(&self) -> &PathBuf {
+        &self.export_shares_path
+    }
+
+    /// Whether an [ExportMode] is set
+    pub fn can_export(&self) -> bool {
+        self.export_mode.is_some()
+    }
+
     /// Check if an exported file exists in the expected path
     /// Succeeds if no exported file exists, returns an error otherwise.
     pub fn check_if_not_exported(&self) -> FileIoResult<()> {
-        if std::path::Path::new(&self.export_path()).exists() {
-            return Err(Exists(self.export_path().clone()));
+        for path in [self.export_path(), self.export_shares_path()] {
+            if path.exists() {
+                return Err(Exists(path.clone()));
+            }
         }
         Ok(())
     }
 
-    /// Creates a file that contains an entropy in it's human-readable form
-    pub(super) fn entropy_to_file(&self, entropy: Entropy) -> FileIoResult<()> {
+    /// Creates a file that contains an entropy in it's human-readable form in `language`,
+    /// encrypted unless the export mode is [ExportMode::Plaintext]
+    pub(super) fn entropy_to_file(&self, entropy: Entropy, language: Language) -> FileIoResult<()> {
+        let export_mode = self.export_mode.as_ref().ok_or(ExportDisabled)?;
+
         // delegate zeroization for entropy; no need to worry about mnemonic, it is cleaned automatically
-        let mnemonic = bip39_from_entropy(entropy)?;
-        let phrase = mnemonic.phrase();
+        let mnemonic = bip39_from_entropy(entropy, language)?;
+        let phrase = Password(mnemonic.phrase().to_string());
 
         // if there is an existing exported file raise an error
         self.check_if_not_exported()?;
 
-        let mut file = std::fs::File::create(self.export_path())?;
-        file.write_all(phrase.as_bytes())?;
-        file.sync_all()?;
+        write_export(self.export_path(), &phrase, export_mode)?;
 
         info!("Mnemonic written in file {:?}", &self.export_path());
         Ok(())
     }
+
+    /// Creates a directory with a file per SLIP-39 share, encrypted unless the export mode is
+    /// [ExportMode::Plaintext]. Shares are numbered from 1.
+    pub(super) fn shares_to_files(&self, shares: Vec<Password>) -> FileIoResult<()> {
+        let export_mode = self.export_mode.as_ref().ok_or(ExportDisabled)?;
+
+        // if there is an existing exported file raise an error
+        self.check_if_not_exported()?;
+
+        std::fs::DirBuilder::new()
+            .mode(EXPORT_SHARES_DIR_MODE)
+            .create(self.export_shares_path())?;
+        for (index, share) in shares.iter().enumerate() {
+            let path = self
+                .export_shares_path()
+                .join(format!("share-{}", index + 1));
+            write_export(&path, share, export_mode)?;
+        }
+
+        info!(
+            "{} mnemonic shares written in directory {:?}",
+            shares.len(),
+            self.export_shares_path()
+        );
+        Ok(())
+    }
+
+    /// Read the file at `path`, which holds either a mnemonic phrase or an [EncryptedExport].
+    /// The words of a phrase may be separated by any whitespace.
+    pub(super) fn read_import_file(path: &Path) -> FileIoResult<ImportContents> {
+        let contents = Password(std::fs::read_to_string(path)?);
+        if let Some(export) = EncryptedExport::from_toml(&contents.0) {
+            return Ok(ImportContents::Encrypted(export));
+        }
+
+        Ok(ImportContents::Phrase(Password(
+            contents.0.split_whitespace().collect::<Vec<_>>().join(" "),
+        )))
+    }
+
+    /// Overwrite the file at `path` with zeros before deleting it, so that its contents don't
+    /// linger in unallocated blocks. Journaling and copy-on-write filesystems may still keep them.
+    pub(super) fn remove_file(path: &Path) -> FileIoResult<()> {
+        let len = std::fs::metadata(path)?.len();
+        let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+        file.write_all(&vec![0; len as usize])?;
+        file.sync_all()?;
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+/// Write `phrase` to a new file at `path` with `export_mode`; fails if the file exists
+fn write_export(path: &Path, phrase: &Password, export_mode: &ExportMode) -> FileIoResult<()> {
+    let contents = match export_mode {
+        ExportMode::Plaintext => phrase.clone(),
+        ExportMode::Encrypted(passphrase) => {
+            Password(EncryptedExport::encrypt(phrase, passphrase)?.to_toml()?)
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(EXPORT_FILE_MODE)
+        .open(path)?;
+    file.write_all(contents.0.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mnemonic::bip39_bindings::{bip39_new_w24, tests::bip39_to_phrase};
-    use std::io::Read;
+    use crate::mnemonic::bip39_bindings::tests::{bip39_new_w24, bip39_to_phrase};
+    use std::{io::Read, os::unix::fs::PermissionsExt};
     use testdir::testdir;
     use tracing_test::traced_test;
 
@@ -70,9 +219,10 @@ mod tests {
     fn test_write() {
         let entropy = bip39_new_w24();
 
-        let io = FileIo::new(testdir!());
+        let io = FileIo::new(testdir!()).with_export_mode(Some(ExportMode::Plaintext));
         let filepath = io.export_path();
-        io.entropy_to_file(entropy.clone()).unwrap();
+        io.entropy_to_file(entropy.clone(), Language::English)
+            .unwrap();
         let expected_content = bip39_to_phrase(entropy).unwrap();
 
         let mut file = std::fs::File::open(filepath).unwrap();
@@ -82,4 +232,76 @@ mod tests {
 
         assert_eq!(file_content, expected_content.0);
     }
+
+    #[test]
+    fn test_write_encrypted() {
+        let entropy = bip39_new_w24();
+        let passphrase = Password("export passphrase".to_string());
+
+        // exports fail without an export mode
+        let io = FileIo::new(testdir!());
+        assert!(matches!(
+            io.entropy_to_file(entropy.clone(), Language::English),
+            Err(ExportDisabled)
+        ));
+        assert!(ExportMode::encrypted(String::new()).is_err());
+
+        let io = io.with_export_mode(Some(ExportMode::encrypted(passphrase.0.clone()).unwrap()));
+        io.entropy_to_file(entropy.clone(), Language::English)
+            .unwrap();
+
+        let export = match FileIo::read_import_file(io.export_path()).unwrap() {
+            ImportContents::Encrypted(export) => export,
+            ImportContents::Phrase(_) => panic!("export is not encrypted"),
+        };
+        assert_eq!(
+            export.decrypt(&passphrase).unwrap().0,
+            bip39_to_phrase(entropy).unwrap().0
+        );
+        let mode = std::fs::metadata(io.export_path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, EXPORT_FILE_MODE);
+
+        // an existing file is never overwritten
+        let phrase = Password("abandon ability able".to_string());
+        assert!(write_export(io.export_path(), &phrase, &ExportMode::Plaintext).is_err());
+    }
+
+    #[test]
+    fn test_write_shares() {
+        let io = FileIo::new(testdir!()).with_export_mode(Some(ExportMode::Plaintext));
+        let shares = vec![
+            Password("share one".to_string()),
+            Password("share two".to_string()),
+        ];
+        io.shares_to_files(shares).unwrap();
+
+        // shares are only accessible to the owner of the tofnd process
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(io.export_shares_path()), EXPORT_SHARES_DIR_MODE);
+        for index in 1..=2 {
+            let path = io.export_shares_path().join(format!("share-{}", index));
+            assert_eq!(mode(&path), EXPORT_FILE_MODE);
+        }
+    }
+
+    #[test]
+    fn test_import_file() {
+        let entropy = bip39_new_w24();
+        let phrase = bip39_to_phrase(entropy).unwrap();
+
+        // extra whitespace is ignored
+        let path = testdir!().join("import");
+        std::fs::write(&path, format!("  {}\n\n", phrase.0.replace(' ', "\t "))).unwrap();
+        match FileIo::read_import_file(&path).unwrap() {
+            ImportContents::Phrase(imported) => assert_eq!(imported.0, phrase.0),
+            ImportContents::Encrypted(_) => panic!("phrase read as an encrypted export"),
+        }
+
+        FileIo::remove_file(&path).unwrap();
+        assert!(!path.exists());
+        assert!(FileIo::read_import_file(&path).is_err());
+    }
 }
@@ -2,16 +2,21 @@
 //!
 //! Currently, the API supports the following [Cmd] commands:
 //!     [Cmd::Existing]: Starts the gRPC daemon existing mnemonic; Fails if mnemonic does not exist.
-//!     [Cmd::Create]: Creates a new mnemonic, inserts it in the kv-store, exports it to a file and exits; Fails if a mnemonic exists.
-//!     [Cmd::Import]: Prompts user to give a new mnemonic, inserts it in the kv-store and exits; Fails if a mnemonic exists or if the provided string is not a valid bip39 mnemonic.
-//!     [Cmd::Export]: Writes the existing mnemonic to a file and exits; Succeeds when there is an existing mnemonic, fails otherwise.
+//!     [Cmd::Create]: Creates a new mnemonic in a [PhraseFormat], inserts it in the kv-store with its [Language], exports it to a file and exits; Fails if a mnemonic exists.
+//!     [Cmd::Import]: Prompts user to give a new mnemonic, or reads it from an [ImportFile], inserts it in the kv-store and exits; Fails if a mnemonic exists or if the provided string is not a valid bip39 mnemonic.
+//!     [Cmd::Export]: Writes the existing mnemonic to a file, in its stored [Language], and exits; Succeeds when there is an existing mnemonic, fails otherwise or if another language is given.
+//!     [Cmd::ExportShares]: Splits the existing mnemonic into SLIP-39 shares, writes them to a directory and exits; Fails if there is no mnemonic.
+//!     [Cmd::ImportShares]: Combines SLIP-39 shares into a mnemonic in the given [Language], inserts it in the kv-store and exits; Fails if the shares are invalid or too few.
+//!     [Cmd::Verify]: Prompts user to give a mnemonic, or reads it from an [ImportFile], and checks it against the stored mnemonics without writing it anywhere; Fails if it matches none of them.
 
 mod bip39_bindings;
 mod cmd_handler;
+mod encrypted_export;
 mod file_io;
 mod results;
+mod slip39_bindings;
 mod types;
 
-pub use cmd_handler::Cmd;
-pub use file_io::FileIo;
-pub use types::Entropy;
+pub use cmd_handler::{Cmd, ImportFile, PhraseFormat, ShareSplit};
+pub use file_io::{ExportMode, FileIo};
+pub use types::{Language, StoredMnemonic};
@@ -15,10 +15,23 @@ pub(super) mod bip39 {
         FromEntropy,
         #[error("invalid phrase")]
         FromPhrase,
+        #[error("invalid word count; expected 12, 15, 18, 21 or 24")]
+        WordCount,
     }
     pub type Bip39Result<Success> = Result<Success, Bip39Error>;
 }
 
+pub(super) mod slip39 {
+    #[derive(thiserror::Error, Debug)]
+    pub enum Slip39Error {
+        #[error("cannot split mnemonic into shares: {0}")]
+        Split(String),
+        #[error("cannot combine shares: {0}")]
+        Combine(String),
+    }
+    pub type Slip39Result<Success> = Result<Success, Slip39Error>;
+}
+
 pub(super) mod file_io {
     #[derive(thiserror::Error, Debug)]
     pub enum FileIoError {
@@ -30,6 +43,18 @@ pub(super) mod file_io {
             "File {0} already exists. Remove file to use `-m existing` or `-m export` commands."
         )]
         Exists(std::path::PathBuf),
+        #[error("An export passphrase is required. Use `--plaintext-export` to export the mnemonic in plaintext.")]
+        ExportDisabled,
+        #[error("Export passphrases do not match")]
+        PassphraseMismatch,
+        #[error("Export passphrase must not be empty")]
+        EmptyPassphrase,
+        #[error("Cannot encrypt export: {0}")]
+        Encryption(String),
+        #[error("Cannot decrypt export: wrong passphrase or corrupted file")]
+        Decryption,
+        #[error("Invalid encrypted export: {0}")]
+        InvalidExport(String),
     }
     pub type FileIoResult<Success> = Result<Success, FileIoError>;
 }
@@ -43,10 +68,16 @@ pub(super) mod mnemonic {
         KvErr(#[from] crate::kv_manager::error::KvError),
         #[error("Invalid mnemonic. See https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki. Bip39 error: {0}")]
         Bip39Error(#[from] super::bip39::Bip39Error),
+        #[error("Invalid SLIP-39 shares. See https://github.com/satoshilabs/slips/blob/master/slip-0039.md. Slip39 error: {0}")]
+        Slip39Error(#[from] super::slip39::Slip39Error),
         #[error("Failed to convert to SecretRecoveryKey")]
         IntoSecretRecoveryKey(#[from] std::array::TryFromSliceError),
         #[error("Password error: {0}")]
         PasswordErr(String),
+        #[error("Phrase does not match any stored mnemonic")]
+        NoMatchingMnemonic,
+        #[error("Mnemonic was stored in {0:?} and can only be exported in that language")]
+        WrongLanguage(crate::mnemonic::Language),
     }
     pub type InnerMnemonicResult<Success> = Result<Success, InnerMnemonicError>;
 
@@ -54,6 +85,10 @@ pub(super) mod mnemonic {
     pub enum MnemonicError {
         #[error("Command not found: {0}")]
         WrongCommand(String),
+        #[error("Invalid share split: {0}")]
+        WrongShareSplit(String),
+        #[error("Invalid word count {0}: expected 12, 15, 18, 21 or 24")]
+        WrongWordCount(usize),
         #[error("Cannot not use existing mnemonic: {0}")]
         ExistingErr(InnerMnemonicError),
         #[error("Cannot create mnemonic: {0}")]
@@ -64,6 +99,12 @@ pub(super) mod mnemonic {
         ExportErr(InnerMnemonicError),
         #[error("Cannot rotate mnemonic: {0}")]
         RotateErr(InnerMnemonicError),
+        #[error("Cannot export mnemonic shares: {0}")]
+        ExportSharesErr(InnerMnemonicError),
+        #[error("Cannot import mnemonic shares: {0}")]
+        ImportSharesErr(InnerMnemonicError),
+        #[error("Cannot verify mnemonic: {0}")]
+        VerifyErr(InnerMnemonicError),
     }
     pub type MnemonicResult<Success> = Result<Success, MnemonicError>;
     pub type SeedResult<Success> = Result<Success, InnerMnemonicError>;
@@ -0,0 +1,103 @@
+//! This module provides wrappers to split an [Entropy] into SLIP-39 shares and to combine them
+//! back, using the sssmc39 https://crates.io/crates/sssmc39 library.
+//!
+//! The entropy is the SLIP-39 master secret, shared in a single group. Like the bip39 seed, the
+//! master secret is not protected by a passphrase, so any `threshold` of the shares recover the
+//! same entropy.
+//!
+//! Zeroization:
+//!   Shares are returned as [Password]s, and the intermediate word lists are zeroized.
+
+use super::results::slip39::{Slip39Error::*, Slip39Result};
+use super::types::{Entropy, Password};
+use zeroize::Zeroize;
+
+// the master secret is shared as is; see MNEMONIC_PASSWORD in cmd_handler
+const SLIP39_PASSPHRASE: &str = "";
+
+// iteration exponent of the master secret encryption. sssmc39 encrypts with the given exponent,
+// but always writes 0 in the shares, so no other exponent recovers the secret.
+const ITERATION_EXPONENT: u8 = 0;
+
+/// split an [Entropy] into `count` share phrases, any `threshold` of which recover it;
+/// takes ownership of entropy and zeroizes it before exit
+pub(super) fn slip39_split(
+    entropy: Entropy,
+    threshold: u8,
+    count: u8,
+) -> Slip39Result<Vec<Password>> {
+    let groups = sssmc39::generate_mnemonics(
+        1,
+        &[(threshold, count)],
+        &entropy.0,
+        SLIP39_PASSPHRASE,
+        ITERATION_EXPONENT,
+    )
+    .map_err(|err| Split(err.to_string()))?;
+
+    let group = groups
+        .first()
+        .ok_or_else(|| Split("no share group generated".to_string()))?;
+    let mut shares = group
+        .mnemonic_list()
+        .map_err(|err| Split(err.to_string()))?;
+
+    let phrases = shares
+        .iter()
+        .map(|words| Password(words.join(" ")))
+        .collect();
+    shares.iter_mut().flatten().for_each(Zeroize::zeroize);
+    Ok(phrases)
+}
+
+/// combine share phrases into the [Entropy] they were split from;
+/// takes ownership of shares and zeroizes them before exit
+pub(super) fn slip39_combine(shares: Vec<Password>) -> Slip39Result<Entropy> {
+    let mut mnemonics: Vec<Vec<String>> = shares
+        .iter()
+        .map(|share| share.0.split_whitespace().map(str::to_string).collect())
+        .collect();
+
+    let master_secret = sssmc39::combine_mnemonics(&mnemonics, SLIP39_PASSPHRASE)
+        .map_err(|err| Combine(err.to_string()));
+    mnemonics.iter_mut().flatten().for_each(Zeroize::zeroize);
+
+    Ok(Entropy(master_secret?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemonic::bip39_bindings::tests::bip39_new_w24;
+
+    #[test]
+    fn round_trip() {
+        let entropy = bip39_new_w24();
+        let shares = slip39_split(entropy.clone(), 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        // a 256-bit secret is shared in 33 words
+        for share in &shares {
+            assert_eq!(share.0.split_whitespace().count(), 33);
+        }
+
+        let some_shares = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(slip39_combine(some_shares).unwrap().0, entropy.0);
+
+        // fewer than threshold shares don't recover the secret
+        let too_few_shares = vec![shares[1].clone(), shares[3].clone()];
+        assert!(slip39_combine(too_few_shares).is_err());
+    }
+
+    #[test]
+    fn invalid_share() {
+        let entropy = bip39_new_w24();
+        let mut shares = slip39_split(entropy, 2, 3).unwrap();
+        shares.truncate(2);
+
+        // a share with a missing word is rejected
+        let mut words: Vec<&str> = shares[1].0.split_whitespace().collect();
+        words.pop();
+        shares[1] = Password(words.join(" "));
+        assert!(slip39_combine(shares).is_err());
+    }
+}
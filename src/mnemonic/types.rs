@@ -11,3 +11,45 @@ pub struct Entropy(pub Vec<u8>);
 #[derive(Zeroize, Clone)]
 #[zeroize(drop)]
 pub struct Password(pub String);
+
+/// Wordlist language of a mnemonic phrase.
+/// Languages are stored with the entropy by index, so new ones must only be appended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    English,
+    ChineseSimplified,
+    ChineseTraditional,
+    French,
+    Italian,
+    Japanese,
+    Korean,
+    Spanish,
+}
+
+impl Language {
+    /// Language codes, as used by tiny-bip39
+    pub const CODES: [&'static str; 8] = ["en", "zh-hans", "zh-hant", "fr", "it", "ja", "ko", "es"];
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        let language = match code {
+            "en" => Self::English,
+            "zh-hans" => Self::ChineseSimplified,
+            "zh-hant" => Self::ChineseTraditional,
+            "fr" => Self::French,
+            "it" => Self::Italian,
+            "ja" => Self::Japanese,
+            "ko" => Self::Korean,
+            "es" => Self::Spanish,
+            _ => return None,
+        };
+        Some(language)
+    }
+}
+
+/// A mnemonic as stored in the kv-store: its entropy and the language its phrase is written in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMnemonic {
+    pub entropy: Entropy,
+    pub language: Language,
+}
@@ -0,0 +1,119 @@
+//! This module handles the batch_sign gRPC.
+//! Entries that share a key are signed with a key pair that is derived only once.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+use super::{
+    error::MultisigResult,
+    hash::message_digest,
+    keypair::KeyPair,
+    policy::{PolicyRequest, PolicyRpc},
+    service::{error_outcome, MultisigService},
+    sign::{sign_with_format, signature_format},
+};
+use crate::proto::{
+    self, batch_sign_result::Outcome, BatchSignEntry, BatchSignError, BatchSignResult,
+    SignatureFormat,
+};
+
+/// entries with the same (key_uid, algorithm, pub_key) are signed by the same key pair
+type KeyId<'a> = (&'a str, i32, &'a [u8]);
+
+/// the result of an entry, and its outcome in the metrics and the audit log
+pub(super) type EntryResult = (BatchSignResult, &'static str);
+
+impl MultisigService {
+    /// Sign every entry of `request` that the signing policy allows `client` to sign.
+    /// The result of each entry is returned at the entry's index.
+    pub(super) async fn handle_batch_sign(
+        &self,
+        request: &proto::BatchSignRequest,
+        client: &str,
+        deadline: Option<Instant>,
+    ) -> Vec<EntryResult> {
+        // derivation errors are kept so that they are reported for every entry of the key
+        let mut key_pairs: HashMap<KeyId, Result<Arc<KeyPair>, BatchSignError>> = HashMap::new();
+        let mut results = Vec::with_capacity(request.entries.len());
+
+        for entry in &request.entries {
+            let checked = check_entry(entry).and_then(|checked| {
+                let approval = self.policy.check(&PolicyRequest {
+                    rpc: PolicyRpc::Sign,
+                    client,
+                    key_uid: &entry.key_uid,
+                    algorithm: entry.algorithm,
+                    hash_mode: Some(entry.hash_mode),
+                })?;
+                Ok((checked, approval))
+            });
+            let ((msg_to_sign, signature_format), approval) = match checked {
+                Ok(checked) => checked,
+                Err(err) => {
+                    let outcome = error_outcome(&err);
+                    results.push((BatchSignResult::error(err.into()), outcome));
+                    continue;
+                }
+            };
+
+            let key_id = (
+                entry.key_uid.as_str(),
+                entry.algorithm,
+                entry.pub_key.as_slice(),
+            );
+            if let Entry::Vacant(vacant) = key_pairs.entry(key_id) {
+                vacant.insert(
+                    self.derive_key_pair(&entry.key_uid, &entry.pub_key, entry.algorithm, deadline)
+                        .await
+                        .map_err(BatchSignError::from),
+                );
+            }
+
+            let signature = match &key_pairs[&key_id] {
+                Ok(key_pair) => {
+                    let key_pair = key_pair.clone();
+                    self.crypto_pool
+                        .run(deadline, move || {
+                            sign_with_format(&key_pair, &msg_to_sign, signature_format)
+                        })
+                        .await
+                        .map_err(BatchSignError::from)
+                }
+                Err(err) => Err(err.clone()),
+            };
+
+            results.push(match signature {
+                Ok(signature) => {
+                    approval.signed();
+                    let result = BatchSignResult {
+                        outcome: Some(Outcome::Signature(signature)),
+                    };
+                    (result, "signature")
+                }
+                // denials are returned before signing, so these are all errors
+                Err(err) => (BatchSignResult::error(err), "error"),
+            });
+        }
+
+        results
+    }
+}
+
+/// Check the parts of `entry` that don't depend on its key
+fn check_entry(entry: &BatchSignEntry) -> MultisigResult<([u8; 32], SignatureFormat)> {
+    Ok((
+        message_digest(&entry.msg_to_sign, entry.hash_mode)?,
+        signature_format(entry.signature_format, entry.algorithm)?,
+    ))
+}
+
+impl BatchSignResult {
+    pub(super) fn error(err: BatchSignError) -> Self {
+        Self {
+            outcome: Some(Outcome::Error(err)),
+        }
+    }
+}
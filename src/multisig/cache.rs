@@ -0,0 +1,174 @@
+//! Bounded cache of derived key pairs.
+//!
+//! Deriving a key pair decrypts a mnemonic, runs the bip39 seed derivation and tofn's keygen,
+//! which takes milliseconds. Signing with a key that isn't in the current mnemonic re-derives the
+//! key of every newer mnemonic first. The [KeyPairCache] keeps the most recently used key pairs,
+//! keyed by the kv store key of the mnemonic they were derived from.
+//!
+//! Mnemonics are moved to a new kv store key on rotation, so the cache is cleared whenever the
+//! mnemonic count changes. Evicted key pairs are zeroized when their last reference is dropped.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use super::keypair::KeyPair;
+use crate::{metrics::KEY_CACHE_LOOKUPS, proto::Algorithm};
+
+/// maximum number of cached key pairs
+pub(super) const KEY_PAIR_CACHE_CAPACITY: usize = 1024;
+
+/// (key_uid, algorithm, kv store key of the mnemonic)
+type CacheKey = (String, Algorithm, String);
+
+struct CacheEntry {
+    key_pair: Arc<KeyPair>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// mnemonic count that the entries were derived with
+    seed_count: usize,
+    /// logical clock of lookups, used to evict the least recently used entry
+    clock: u64,
+}
+
+/// Least recently used cache of key pairs; clones share the same entries
+#[derive(Clone)]
+pub(super) struct KeyPairCache {
+    capacity: usize,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl KeyPairCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Arc::new(Mutex::new(CacheState::default())),
+        }
+    }
+
+    /// Get the key pair of `key_uid` derived from the mnemonic stored at `seed_key`.
+    /// `seed_count` is the current number of mnemonics; a different count clears the cache.
+    pub(super) fn get(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        seed_key: &str,
+        seed_count: usize,
+    ) -> Option<Arc<KeyPair>> {
+        let mut state = self.state.lock().expect("key pair cache lock poisoned");
+        state.invalidate_if_rotated(seed_count);
+
+        state.clock += 1;
+        let clock = state.clock;
+        let key_pair = state
+            .entries
+            .get_mut(&(key_uid.to_string(), algorithm, seed_key.to_string()))
+            .map(|entry| {
+                entry.last_used = clock;
+                entry.key_pair.clone()
+            });
+
+        KEY_CACHE_LOOKUPS
+            .with_label_values(&[if key_pair.is_some() { "hit" } else { "miss" }])
+            .inc();
+        key_pair
+    }
+
+    pub(super) fn insert(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        seed_key: &str,
+        seed_count: usize,
+        key_pair: Arc<KeyPair>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("key pair cache lock poisoned");
+        state.invalidate_if_rotated(seed_count);
+
+        let key = (key_uid.to_string(), algorithm, seed_key.to_string());
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let least_recently_used = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                state.entries.remove(&least_recently_used);
+            }
+        }
+
+        state.clock += 1;
+        let last_used = state.clock;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                key_pair,
+                last_used,
+            },
+        );
+    }
+}
+
+impl CacheState {
+    fn invalidate_if_rotated(&mut self, seed_count: usize) {
+        if self.seed_count != seed_count {
+            self.entries.clear();
+            self.seed_count = seed_count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tofn::sdk::api::SecretRecoveryKey;
+
+    const ALGORITHM: Algorithm = Algorithm::Ed25519;
+
+    fn key_pair(key_uid: &str) -> Arc<KeyPair> {
+        let secret_recovery_key: SecretRecoveryKey = [7; 64].as_slice().try_into().unwrap();
+        Arc::new(KeyPair::new(&secret_recovery_key, key_uid.as_bytes(), ALGORITHM).unwrap())
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = KeyPairCache::new(2);
+        for key_uid in ["key-1", "key-2"] {
+            cache.insert(key_uid, ALGORITHM, "mnemonic", 1, key_pair(key_uid));
+        }
+
+        // key-1 is used more recently than key-2
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic", 1).is_some());
+        cache.insert("key-3", ALGORITHM, "mnemonic", 1, key_pair("key-3"));
+
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic", 1).is_some());
+        assert!(cache.get("key-2", ALGORITHM, "mnemonic", 1).is_none());
+        assert!(cache.get("key-3", ALGORITHM, "mnemonic", 1).is_some());
+
+        // keys are cached per algorithm and mnemonic
+        assert!(cache
+            .get("key-1", Algorithm::Ecdsa, "mnemonic", 1)
+            .is_none());
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic_1", 1).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_on_rotation() {
+        let cache = KeyPairCache::new(2);
+        cache.insert("key-1", ALGORITHM, "mnemonic", 1, key_pair("key-1"));
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic", 1).is_some());
+
+        // after a rotation, `mnemonic` is a different mnemonic
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic", 2).is_none());
+        assert!(cache.get("key-1", ALGORITHM, "mnemonic", 1).is_none());
+    }
+}
@@ -0,0 +1,146 @@
+//! Thread pool for CPU-bound key derivation and signing.
+//!
+//! Deriving a key runs the bip39 seed derivation and tofn's keygen, which take milliseconds. On
+//! the tokio worker threads, they would delay every other request and the health checks. The
+//! [CryptoPool] runs them on dedicated threads, and drops jobs whose gRPC deadline expired while
+//! they were queued.
+
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tokio::sync::oneshot;
+use tonic::metadata::MetadataMap;
+
+use super::error::{MultisigError, MultisigResult};
+use crate::TofndResult;
+
+/// metadata header of the gRPC timeout of a request
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Pool of threads that run crypto jobs; clones share the same threads
+#[derive(Clone)]
+pub(super) struct CryptoPool {
+    pool: Arc<ThreadPool>,
+}
+
+impl CryptoPool {
+    /// Create a pool of `threads` threads, or of one thread per CPU if [None]
+    pub(super) fn new(threads: Option<usize>) -> TofndResult<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .thread_name(|index| format!("tofnd-crypto-{}", index))
+            .build()?;
+
+        Ok(Self {
+            pool: Arc::new(pool),
+        })
+    }
+
+    /// Run `job` on the pool, unless `deadline` passes before it starts
+    pub(super) async fn run<F, T>(&self, deadline: Option<Instant>, job: F) -> MultisigResult<T>
+    where
+        F: FnOnce() -> MultisigResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        check_deadline(deadline)?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.pool.spawn(move || {
+            let result = check_deadline(deadline).and_then(|_| {
+                catch_unwind(AssertUnwindSafe(job))
+                    .unwrap_or_else(|_| Err(anyhow!("crypto job panicked").into()))
+            });
+            // the request was dropped if the receiver is gone
+            let _ = sender.send(result);
+        });
+
+        receiver
+            .await
+            .map_err(|_| anyhow!("crypto pool dropped the job"))?
+    }
+}
+
+/// Deadline of a request received at `received`, from its `grpc-timeout` header
+pub(super) fn request_deadline(metadata: &MetadataMap, received: Instant) -> Option<Instant> {
+    let timeout = metadata.get(GRPC_TIMEOUT_HEADER)?.to_str().ok()?;
+    Some(received + parse_grpc_timeout(timeout)?)
+}
+
+/// Parse a `grpc-timeout` value: at most 8 digits followed by a unit.
+/// See https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md
+fn parse_grpc_timeout(timeout: &str) -> Option<Duration> {
+    if !timeout.is_ascii() || timeout.len() < 2 || timeout.len() > 9 {
+        return None;
+    }
+    let (value, unit) = timeout.split_at(timeout.len() - 1);
+    if !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let value: u64 = value.parse().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+fn check_deadline(deadline: Option<Instant>) -> MultisigResult<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(MultisigError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(
+            parse_grpc_timeout("99999999m"),
+            Some(Duration::from_millis(99999999))
+        );
+        assert_eq!(parse_grpc_timeout("100n"), Some(Duration::from_nanos(100)));
+
+        for invalid in ["", "S", "10", "10s", "-1S", "+1S", "1é", "123456789S"] {
+            assert_eq!(parse_grpc_timeout(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let pool = CryptoPool::new(Some(1)).unwrap();
+
+        let thread_name = pool
+            .run(None, || Ok(std::thread::current().name().map(String::from)))
+            .await
+            .unwrap();
+        assert_eq!(thread_name.as_deref(), Some("tofnd-crypto-0"));
+
+        // expired requests are not run
+        let expired = Some(Instant::now());
+        let result = pool
+            .run(expired, || -> MultisigResult<()> { panic!("ran") })
+            .await;
+        assert!(matches!(result, Err(MultisigError::DeadlineExceeded)));
+
+        // panics are returned as errors
+        let result = pool
+            .run(None, || -> MultisigResult<()> { panic!("panic") })
+            .await;
+        assert!(matches!(result, Err(MultisigError::Internal(_))));
+    }
+}
@@ -0,0 +1,115 @@
+//! Custom error types for [crate::multisig].
+//!
+//! Keygen and sign failures are returned to clients in the `error` of the response, with an
+//! [proto::ErrorReason] that identifies the error category, so clients don't need to match on
+//! error messages. Requests denied by the signing policy are not processed, and fail with a
+//! [tonic::Status] with a gRPC code and a `google.rpc.ErrorInfo` detail whose `reason` is the
+//! name of the same category.
+
+use std::collections::HashMap;
+
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::proto;
+
+/// minimum key uid length accepted by tofn's keygen
+pub(super) const MIN_KEY_UID_LEN: usize = 4;
+
+/// maximum key uid length accepted by tofn's keygen
+pub(super) const MAX_KEY_UID_LEN: usize = 256;
+
+/// domain of the `google.rpc.ErrorInfo` details
+const ERROR_DOMAIN: &str = "tofnd";
+
+#[derive(thiserror::Error, Debug)]
+pub enum MultisigError {
+    #[error("Invalid algorithm: {0}")]
+    InvalidAlgorithm(i32),
+    #[error("key uid {0:?} is too short, expected at least {MIN_KEY_UID_LEN} bytes")]
+    KeyUidTooShort(String),
+    #[error("key uid is too long, expected at most {MAX_KEY_UID_LEN} bytes, got {0}")]
+    KeyUidTooLong(usize),
+    #[error("message digest must be 32 bytes, got {0}")]
+    InvalidDigestLength(usize),
+    #[error("Invalid hash mode: {0}")]
+    InvalidHashMode(i32),
+    #[error("Invalid signature format: {0}")]
+    InvalidSignatureFormat(String),
+    #[error("could not find a matching mnemonic for key {0:?}")]
+    NoMatchingMnemonic(String),
+    #[error("the deadline of the request expired before it was processed")]
+    DeadlineExceeded,
+    #[error("denied by the signing policy: {0}")]
+    PolicyDenied(String),
+    #[error("kv store error: {0}")]
+    KvErr(String),
+    #[error("cannot write the audit record: {0}")]
+    AuditErr(String),
+    #[error("{0}")]
+    Internal(#[from] anyhow::Error),
+}
+pub type MultisigResult<Success> = Result<Success, MultisigError>;
+
+impl MultisigError {
+    /// Error category, returned in the `error_reason` of responses
+    pub fn error_reason(&self) -> proto::ErrorReason {
+        use proto::ErrorReason;
+        match self {
+            Self::InvalidAlgorithm(_) => ErrorReason::InvalidAlgorithm,
+            Self::KeyUidTooShort(_) => ErrorReason::KeyUidTooShort,
+            Self::KeyUidTooLong(_) => ErrorReason::KeyUidTooLong,
+            Self::InvalidDigestLength(_) => ErrorReason::InvalidDigestLength,
+            Self::InvalidHashMode(_) => ErrorReason::InvalidHashMode,
+            Self::InvalidSignatureFormat(_) => ErrorReason::InvalidSignatureFormat,
+            Self::NoMatchingMnemonic(_) => ErrorReason::NoMatchingMnemonic,
+            Self::DeadlineExceeded => ErrorReason::DeadlineExceeded,
+            Self::PolicyDenied(_) => ErrorReason::PolicyDenied,
+            Self::KvErr(_) => ErrorReason::KvFailure,
+            Self::AuditErr(_) => ErrorReason::AuditFailure,
+            Self::Internal(_) => ErrorReason::Internal,
+        }
+    }
+
+    /// Name of the error category without its `ERROR_REASON_` prefix, e.g. `POLICY_DENIED`,
+    /// returned as the `reason` of the `google.rpc.ErrorInfo` detail
+    pub fn reason(&self) -> &'static str {
+        self.error_reason()
+            .as_str_name()
+            .trim_start_matches("ERROR_REASON_")
+    }
+
+    fn code(&self) -> Code {
+        match self {
+            Self::InvalidAlgorithm(_)
+            | Self::KeyUidTooShort(_)
+            | Self::KeyUidTooLong(_)
+            | Self::InvalidDigestLength(_)
+            | Self::InvalidHashMode(_)
+            | Self::InvalidSignatureFormat(_) => Code::InvalidArgument,
+            Self::NoMatchingMnemonic(_) => Code::NotFound,
+            Self::DeadlineExceeded => Code::DeadlineExceeded,
+            Self::PolicyDenied(_) => Code::PermissionDenied,
+            Self::KvErr(_) | Self::AuditErr(_) => Code::Unavailable,
+            Self::Internal(_) => Code::Internal,
+        }
+    }
+
+    pub fn into_status(self) -> Status {
+        Status::with_error_details(
+            self.code(),
+            self.to_string(),
+            ErrorDetails::with_error_info(self.reason(), ERROR_DOMAIN, HashMap::new()),
+        )
+    }
+}
+
+/// Errors of single entries of a batch are returned as part of the response
+impl From<MultisigError> for proto::BatchSignError {
+    fn from(err: MultisigError) -> Self {
+        Self {
+            reason: err.error_reason().into(),
+            message: err.to_string(),
+        }
+    }
+}
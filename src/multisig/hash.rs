@@ -0,0 +1,85 @@
+//! Message hashing of sign requests.
+//!
+//! By default, `msg_to_sign` is a 32-byte digest that is signed as is. Other [HashMode]s hash the
+//! raw payload inside tofnd, so that a signature is only produced for a payload that tofnd saw.
+
+use blake2::{digest::consts::U32, Blake2b};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+use super::error::{MultisigError, MultisigResult};
+use crate::proto::HashMode;
+
+/// prefix of EIP-191 personal messages, followed by the decimal length of the message
+const EIP191_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
+
+/// Compute the 32-byte digest of `msg_to_sign` according to `hash_mode`
+pub(super) fn message_digest(msg_to_sign: &[u8], hash_mode: i32) -> MultisigResult<[u8; 32]> {
+    let hash_mode =
+        HashMode::try_from(hash_mode).map_err(|_| MultisigError::InvalidHashMode(hash_mode))?;
+
+    Ok(match hash_mode {
+        HashMode::Raw => msg_to_sign
+            .try_into()
+            .map_err(|_| MultisigError::InvalidDigestLength(msg_to_sign.len()))?,
+        HashMode::Keccak256 => Keccak256::digest(msg_to_sign).into(),
+        HashMode::Sha256 => Sha256::digest(msg_to_sign).into(),
+        HashMode::Eip191 => Keccak256::new()
+            .chain_update(EIP191_PREFIX)
+            .chain_update(msg_to_sign.len().to_string())
+            .chain_update(msg_to_sign)
+            .finalize()
+            .into(),
+        HashMode::Blake2b256 => Blake2b::<U32>::digest(msg_to_sign).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_digest() {
+        let digest = message_digest(&[1; 32], HashMode::Raw as i32).unwrap();
+        assert_eq!(digest, [1; 32]);
+
+        // known answers for an empty message
+        let cases = [
+            (
+                HashMode::Keccak256,
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            ),
+            (
+                HashMode::Sha256,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                HashMode::Blake2b256,
+                "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+            ),
+        ];
+        for (hash_mode, expected) in cases {
+            let digest = message_digest(&[], hash_mode as i32).unwrap();
+            assert_eq!(hex::encode(digest), expected);
+        }
+
+        // personal_sign("hello")
+        let digest = message_digest(b"hello", HashMode::Eip191 as i32).unwrap();
+        assert_eq!(
+            hex::encode(digest),
+            "50b2c43fd39106bafbba0da34fc430e1f91e3c96ea2acee2bc34119f92b37750"
+        );
+    }
+
+    #[test]
+    fn test_message_digest_fail() {
+        assert!(matches!(
+            message_digest(&[1; 31], HashMode::Raw as i32),
+            Err(MultisigError::InvalidDigestLength(31))
+        ));
+        assert!(matches!(
+            message_digest(&[1; 32], 42),
+            Err(MultisigError::InvalidHashMode(42))
+        ));
+    }
+}
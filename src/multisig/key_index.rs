@@ -0,0 +1,370 @@
+//! Index of the mnemonic that derives each key.
+//!
+//! Without the index, signing for a `pub_key` re-derives the key from every mnemonic until one
+//! matches. Keygen records the fingerprint of the mnemonic that derives each
+//! (algorithm, key_uid, pub_key) in the kv store, so that sign can go straight to that mnemonic.
+//! Fingerprints don't change when mnemonics are rotated; they are mapped to the kv store keys of
+//! the mnemonics in memory.
+//!
+//! Keys that are not indexed, e.g. keys generated by older versions of tofnd, are still found by
+//! trying every mnemonic. `tofnd index rebuild` indexes them.
+//!
+//! Keygen also records each (algorithm, key_uid), so that key presence checks without a `pub_key`
+//! can tell whether a key was generated. Kv stores created by older versions of tofnd may hold keys
+//! without a record; only stores whose mnemonic was created with records are marked complete.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{keygen::check_key_uid, keypair::KeyPair, service::MultisigService};
+use crate::{kv_manager::KvManager, proto::Algorithm, TofndResult};
+
+// logging
+use tracing::{info, warn};
+
+/// prefix of the kv store keys of index records
+const KEY_INDEX_PREFIX: &str = "key_index_";
+
+/// prefix of the kv store keys of keygen records
+const KEYGEN_RECORD_PREFIX: &str = "keygen_record_";
+
+/// kv store key of the marker that every generated key has a keygen record
+const KEYGEN_RECORDS_COMPLETE_KEY: &str = "keygen_records_complete";
+
+type Fingerprint = [u8; 32];
+
+/// kv store key of the index record of (algorithm, key_uid, pub_key)
+fn index_key(key_uid: &str, algorithm: Algorithm, pub_key: &[u8]) -> String {
+    let hash = Sha256::new()
+        .chain_update((algorithm as i32).to_be_bytes())
+        .chain_update((key_uid.len() as u64).to_be_bytes())
+        .chain_update(key_uid)
+        .chain_update(pub_key)
+        .finalize();
+
+    format!("{}{}", KEY_INDEX_PREFIX, hex::encode(hash))
+}
+
+/// kv store key of the keygen record of (algorithm, key_uid)
+fn keygen_record_key(key_uid: &str, algorithm: Algorithm) -> String {
+    let hash = Sha256::new()
+        .chain_update((algorithm as i32).to_be_bytes())
+        .chain_update(key_uid)
+        .finalize();
+
+    format!("{}{}", KEYGEN_RECORD_PREFIX, hex::encode(hash))
+}
+
+/// implement key index functions for KvManager
+impl KvManager {
+    /// Record that the mnemonic with `fingerprint` derives `pub_key`.
+    /// Returns `false` if the key is already indexed.
+    async fn put_key_index(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        pub_key: &[u8],
+        fingerprint: &Fingerprint,
+    ) -> TofndResult<bool> {
+        // keys are derived deterministically, so an existing record is the same record
+        Ok(self
+            .kv()
+            .insert(
+                &index_key(key_uid, algorithm, pub_key),
+                fingerprint.to_vec(),
+            )
+            .await?)
+    }
+
+    /// Record that a key of `key_uid` was generated
+    async fn put_keygen_record(&self, key_uid: &str, algorithm: Algorithm) -> TofndResult<()> {
+        self.kv()
+            .insert(&keygen_record_key(key_uid, algorithm), vec![])
+            .await?;
+        Ok(())
+    }
+
+    /// Whether a key of `key_uid` was generated
+    pub(super) async fn has_keygen_record(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+    ) -> TofndResult<bool> {
+        Ok(self
+            .kv()
+            .exists(&keygen_record_key(key_uid, algorithm))
+            .await?)
+    }
+
+    /// Mark that every key generated with this kv store has a keygen record.
+    /// Only call it when no key can have been generated before, i.e. for a new mnemonic.
+    pub(crate) async fn mark_keygen_records_complete(&self) -> TofndResult<()> {
+        self.kv()
+            .insert(KEYGEN_RECORDS_COMPLETE_KEY, vec![])
+            .await?;
+        Ok(())
+    }
+
+    /// Whether every key generated with this kv store has a keygen record
+    pub(super) async fn keygen_records_complete(&self) -> TofndResult<bool> {
+        Ok(self.kv().exists(KEYGEN_RECORDS_COMPLETE_KEY).await?)
+    }
+
+    /// Fingerprint of the mnemonic that derives `pub_key`, if the key is indexed
+    async fn get_key_index(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        pub_key: &[u8],
+    ) -> TofndResult<Option<Fingerprint>> {
+        let key = index_key(key_uid, algorithm, pub_key);
+        if !self.kv().exists(&key).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(self.kv().get(&key).await?.as_slice().try_into()?))
+    }
+
+    /// Index the keys of `key_uids` for every algorithm and mnemonic.
+    /// Keygen records are not written, because a key uid doesn't tell which algorithms were used.
+    /// Returns the number of keys that were not indexed before.
+    pub async fn rebuild_key_index(&self, key_uids: &[String]) -> TofndResult<usize> {
+        for key_uid in key_uids {
+            check_key_uid(key_uid)?;
+        }
+
+        let mut indexed = 0;
+        for seed_key in self.seed_key_iter().await? {
+            let fingerprint = self.seed_fingerprint(&seed_key).await?;
+            let secret_recovery_key = self.get_seed(&seed_key).await?;
+
+            for key_uid in key_uids {
+                for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
+                    let key_pair =
+                        KeyPair::new(&secret_recovery_key, key_uid.as_bytes(), algorithm)?;
+                    let pub_key = key_pair.encoded_verifying_key();
+
+                    if self
+                        .put_key_index(key_uid, algorithm, &pub_key, &fingerprint)
+                        .await?
+                    {
+                        indexed += 1;
+                    }
+                }
+            }
+
+            info!("Indexed the keys of mnemonic <{}>", seed_key);
+        }
+
+        Ok(indexed)
+    }
+}
+
+#[derive(Default)]
+struct SeedKeysState {
+    /// mnemonic count that the map was built with
+    seed_count: usize,
+    by_fingerprint: HashMap<Fingerprint, String>,
+}
+
+/// kv store keys of the mnemonics by fingerprint; clones share the same map
+#[derive(Clone, Default)]
+pub(super) struct SeedKeys {
+    state: Arc<Mutex<SeedKeysState>>,
+}
+
+impl SeedKeys {
+    /// Find the key of the mnemonic with `fingerprint` among `seed_keys`, the keys of all mnemonics.
+    /// The map is rebuilt when the number of mnemonics changes, i.e. after a rotation.
+    async fn find(
+        &self,
+        kv_manager: &KvManager,
+        seed_keys: &[String],
+        fingerprint: &Fingerprint,
+    ) -> TofndResult<Option<String>> {
+        {
+            let state = self.state.lock().expect("seed keys lock poisoned");
+            if state.seed_count == seed_keys.len() {
+                return Ok(state.by_fingerprint.get(fingerprint).cloned());
+            }
+        }
+
+        // the same mnemonic may be imported twice; prefer the latest key
+        let mut by_fingerprint = HashMap::new();
+        for seed_key in seed_keys {
+            by_fingerprint
+                .entry(kv_manager.seed_fingerprint(seed_key).await?)
+                .or_insert_with(|| seed_key.clone());
+        }
+        let seed_key = by_fingerprint.get(fingerprint).cloned();
+
+        *self.state.lock().expect("seed keys lock poisoned") = SeedKeysState {
+            seed_count: seed_keys.len(),
+            by_fingerprint,
+        };
+        Ok(seed_key)
+    }
+}
+
+impl MultisigService {
+    /// Record and index the key of `key_uid` derived from the current mnemonic.
+    /// Failures are only logged, because sign falls back to trying every mnemonic.
+    pub(super) async fn index_current_key(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        pub_key: &[u8],
+    ) {
+        let result: TofndResult<bool> = async {
+            self.kv_manager
+                .put_keygen_record(key_uid, algorithm)
+                .await?;
+            let fingerprint = self.kv_manager.current_seed_fingerprint().await?;
+            self.kv_manager
+                .put_key_index(key_uid, algorithm, pub_key, &fingerprint)
+                .await
+        }
+        .await;
+
+        if let Err(err) = result {
+            warn!("[{}] cannot index key: {}", key_uid, err);
+        }
+    }
+
+    /// Key of the mnemonic that derives `pub_key` among `seed_keys`, if the key is indexed
+    pub(super) async fn indexed_seed_key(
+        &self,
+        key_uid: &str,
+        algorithm: Algorithm,
+        pub_key: &[u8],
+        seed_keys: &[String],
+    ) -> Option<String> {
+        let result: TofndResult<Option<String>> = async {
+            match self
+                .kv_manager
+                .get_key_index(key_uid, algorithm, pub_key)
+                .await?
+            {
+                Some(fingerprint) => {
+                    self.seed_keys
+                        .find(&self.kv_manager, seed_keys, &fingerprint)
+                        .await
+                }
+                None => Ok(None),
+            }
+        }
+        .await;
+
+        result.unwrap_or_else(|err| {
+            warn!("[{}] cannot read key index: {}", key_uid, err);
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encrypted_sled::get_test_password,
+        mnemonic::{Cmd, ExportMode, PhraseFormat},
+        proto::{
+            self,
+            key_presence_response::Response::{Absent, Present},
+        },
+    };
+    use testdir::testdir;
+    use tracing_test::traced_test;
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_rebuild_key_index() {
+        let kv_manager = KvManager::new(testdir!(), get_test_password())
+            .unwrap()
+            .with_export_mode(Some(ExportMode::Plaintext))
+            .handle_mnemonic(&Cmd::Create(PhraseFormat::default()))
+            .await
+            .unwrap();
+        std::fs::remove_file(kv_manager.io().export_path()).unwrap();
+        let kv_manager = kv_manager.handle_mnemonic(&Cmd::Rotate).await.unwrap();
+
+        let key_uids = vec!["key-1".to_string(), "key-2".to_string()];
+        let indexed = kv_manager.rebuild_key_index(&key_uids).await.unwrap();
+        // 2 key uids, 3 algorithms, 2 mnemonics
+        assert_eq!(indexed, 12);
+        // rebuilding again only indexes new keys
+        assert_eq!(kv_manager.rebuild_key_index(&key_uids).await.unwrap(), 0);
+
+        // keys of the rotated out mnemonic point to its new kv store key
+        let rotated_key = "mnemonic_1";
+        let secret_recovery_key = kv_manager.get_seed(rotated_key).await.unwrap();
+        let pub_key = KeyPair::new(&secret_recovery_key, b"key-1", Algorithm::Ecdsa)
+            .unwrap()
+            .encoded_verifying_key();
+
+        let service = MultisigService::new(kv_manager.clone(), Default::default()).unwrap();
+        let seed_keys = kv_manager.seed_key_iter().await.unwrap();
+        assert_eq!(
+            service
+                .indexed_seed_key("key-1", Algorithm::Ecdsa, &pub_key, &seed_keys)
+                .await
+                .as_deref(),
+            Some(rotated_key)
+        );
+        assert!(service
+            .indexed_seed_key("key-3", Algorithm::Ecdsa, &pub_key, &seed_keys)
+            .await
+            .is_none());
+
+        // indexed keys are not recorded as generated
+        assert!(!kv_manager
+            .has_keygen_record("key-2", Algorithm::Ecdsa)
+            .await
+            .unwrap());
+
+        // key uids must be valid
+        assert!(kv_manager
+            .rebuild_key_index(&["k".to_string()])
+            .await
+            .is_err());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_key_presence_without_records() {
+        let kv_manager = KvManager::new(testdir!(), get_test_password())
+            .unwrap()
+            .with_export_mode(Some(ExportMode::Plaintext))
+            .handle_mnemonic(&Cmd::Create(PhraseFormat::default()))
+            .await
+            .unwrap();
+        let service = MultisigService::new(kv_manager.clone(), Default::default()).unwrap();
+        let request = proto::KeyPresenceRequest {
+            key_uid: "key-1".to_string(),
+            pub_key: vec![],
+            algorithm: Algorithm::Ecdsa as i32,
+        };
+
+        // a new mnemonic has a record of every key
+        assert!(kv_manager.keygen_records_complete().await.unwrap());
+        assert_eq!(
+            service.handle_key_presence(&request, None).await.unwrap(),
+            Absent
+        );
+
+        // keys of older kv stores are derived from the current mnemonic
+        kv_manager
+            .kv()
+            .delete(KEYGEN_RECORDS_COMPLETE_KEY)
+            .await
+            .unwrap();
+        assert_eq!(
+            service.handle_key_presence(&request, None).await.unwrap(),
+            Present
+        );
+    }
+}
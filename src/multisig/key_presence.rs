@@ -1,38 +1,70 @@
 //! This module handles the key_presence gRPC.
 //! Request includes [proto::message_in::Data::KeyPresenceRequest] struct and encrypted recovery info.
 
-use super::service::MultisigService;
+use super::{
+    error::{MultisigError, MultisigResult},
+    keygen::check_key_uid,
+    service::MultisigService,
+};
 
 // logging
 use tracing::debug;
 
-// error handling
-use crate::{
-    proto::{self, Algorithm},
-    TofndResult,
+use crate::proto::{
+    self,
+    key_presence_response::Response::{Absent, Present},
+    Algorithm,
 };
-use anyhow::anyhow;
+use std::time::Instant;
 
 impl MultisigService {
     pub(super) async fn handle_key_presence(
         &self,
-        request: proto::KeyPresenceRequest,
-    ) -> TofndResult<proto::key_presence_response::Response> {
+        request: &proto::KeyPresenceRequest,
+        deadline: Option<Instant>,
+    ) -> MultisigResult<proto::key_presence_response::Response> {
         let algorithm = Algorithm::try_from(request.algorithm)
-            .map_err(|_| anyhow!("Invalid algorithm: {}", request.algorithm))?;
-
-        // check if mnemonic is available
-        let _ = self
-            .find_matching_seed(&request.key_uid, &request.pub_key, algorithm)
-            .await?;
-
-        // key presence for multisig always returns `Present`.
-        // this is done in order to not break compatibility with axelar-core
-        // TODO: better handling for multisig key presence.
-        debug!(
-            "[{}] key presence check for multisig always return Present",
-            request.key_uid
-        );
-        Ok(proto::key_presence_response::Response::Present)
+            .map_err(|_| MultisigError::InvalidAlgorithm(request.algorithm))?;
+
+        // no key can be generated for an invalid key uid
+        if check_key_uid(&request.key_uid).is_err() {
+            debug!("[{}] key uid is invalid", request.key_uid);
+            return Ok(Absent);
+        }
+
+        // without a pub key, only keys generated by this tofnd are known
+        if request.pub_key.is_empty() {
+            if self
+                .kv_manager
+                .has_keygen_record(&request.key_uid, algorithm)
+                .await?
+            {
+                return Ok(Present);
+            }
+            if self.kv_manager.keygen_records_complete().await? {
+                return Ok(Absent);
+            }
+
+            // keys of older kv stores may have no record; fall back to deriving the key
+            debug!(
+                "[{}] no keygen record in a kv store that predates them",
+                request.key_uid
+            );
+            self.find_matching_key_pair(&request.key_uid, &[], algorithm, deadline)
+                .await?;
+            return Ok(Present);
+        }
+
+        match self
+            .find_matching_key_pair(&request.key_uid, &request.pub_key, algorithm, deadline)
+            .await
+        {
+            Ok(_) => Ok(Present),
+            Err(MultisigError::NoMatchingMnemonic(_)) => {
+                debug!("[{}] no mnemonic derives the pub key", request.key_uid);
+                Ok(Absent)
+            }
+            Err(err) => Err(err),
+        }
     }
 }
@@ -1,19 +1,46 @@
-use super::{keypair::KeyPair, service::MultisigService};
-use crate::{
-    proto::{Algorithm, KeygenRequest},
-    TofndResult,
+use super::{
+    error::{MultisigError, MultisigResult, MAX_KEY_UID_LEN, MIN_KEY_UID_LEN},
+    service::MultisigService,
 };
-use anyhow::anyhow;
+use crate::proto::{Algorithm, KeygenRequest};
+use std::time::Instant;
 
 impl MultisigService {
-    pub(super) async fn handle_keygen(&self, request: &KeygenRequest) -> TofndResult<Vec<u8>> {
+    pub(super) async fn handle_keygen(
+        &self,
+        request: &KeygenRequest,
+        deadline: Option<Instant>,
+    ) -> MultisigResult<Vec<u8>> {
         let algorithm = Algorithm::try_from(request.algorithm)
-            .map_err(|_| anyhow!("Invalid algorithm: {}", request.algorithm))?;
-        let secret_recovery_key = self.kv_manager.seed().await?;
+            .map_err(|_| MultisigError::InvalidAlgorithm(request.algorithm))?;
+        check_key_uid(&request.key_uid)?;
 
-        Ok(
-            KeyPair::new(&secret_recovery_key, request.key_uid.as_bytes(), algorithm)?
-                .encoded_verifying_key(),
-        )
+        // the index must point to the mnemonic that generated the key
+        let _guard = self.kv_manager.mnemonic_lock().read().await;
+        let mnemonic = self
+            .kv_manager
+            .mnemonic()
+            .await
+            .map_err(|err| MultisigError::KvErr(err.to_string()))?;
+
+        let pub_key = self
+            .key_pair_from_mnemonic(mnemonic, &request.key_uid, algorithm, deadline)
+            .await?
+            .encoded_verifying_key();
+        self.index_current_key(&request.key_uid, algorithm, &pub_key)
+            .await;
+
+        Ok(pub_key)
+    }
+}
+
+/// Reject key uids that tofn can't use as a session nonce
+pub(super) fn check_key_uid(key_uid: &str) -> MultisigResult<()> {
+    if key_uid.len() < MIN_KEY_UID_LEN {
+        return Err(MultisigError::KeyUidTooShort(key_uid.to_string()));
+    }
+    if key_uid.len() > MAX_KEY_UID_LEN {
+        return Err(MultisigError::KeyUidTooLong(key_uid.len()));
     }
+    Ok(())
 }
@@ -1,13 +1,27 @@
 use crate::{proto::Algorithm, TofndResult};
 use anyhow::anyhow;
+use k256::{
+    ecdsa::{RecoveryId, Signature, VerifyingKey},
+    schnorr,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
 use tofn::{
     ecdsa, ed25519,
     sdk::api::{MessageDigest, SecretRecoveryKey},
 };
 
+/// Domain of the session nonces of BIP-340 keys
+const SCHNORR_DOMAIN: &[u8] = b"tofnd bip340";
+
+/// `v` of a recoverable signature is the recovery id plus 27, as expected by `ecrecover`
+const RECOVERY_ID_OFFSET: u8 = 27;
+
 pub enum KeyPair {
     Ecdsa(ecdsa::KeyPair),
     Ed25519(ed25519::KeyPair),
+    Schnorr(schnorr::SigningKey),
 }
 
 impl KeyPair {
@@ -31,6 +45,10 @@ impl KeyPair {
 
                 Self::Ed25519(key_pair)
             }
+
+            Algorithm::Schnorr => {
+                Self::Schnorr(schnorr_keygen(secret_recovery_key, session_nonce)?)
+            }
         })
     }
 
@@ -38,14 +56,91 @@ impl KeyPair {
         match self {
             Self::Ecdsa(key_pair) => key_pair.encoded_verifying_key().into(),
             Self::Ed25519(key_pair) => key_pair.encoded_verifying_key().into(),
+            // 32-byte x-only public key
+            Self::Schnorr(signing_key) => signing_key.verifying_key().to_bytes().to_vec(),
         }
     }
 
-    pub fn sign(&self, msg_to_sign: &MessageDigest) -> TofndResult<Vec<u8>> {
+    pub fn sign(&self, msg_to_sign: &[u8; 32]) -> TofndResult<Vec<u8>> {
         match self {
-            Self::Ecdsa(key_pair) => ecdsa::sign(key_pair.signing_key(), msg_to_sign),
-            Self::Ed25519(key_pair) => ed25519::sign(key_pair, msg_to_sign),
+            Self::Ecdsa(key_pair) => {
+                ecdsa::sign(key_pair.signing_key(), &message_digest(msg_to_sign)?)
+                    .map_err(|_| anyhow!("signing failed"))
+            }
+            Self::Ed25519(key_pair) => ed25519::sign(key_pair, &message_digest(msg_to_sign)?)
+                .map_err(|_| anyhow!("signing failed")),
+            Self::Schnorr(signing_key) => schnorr_sign(signing_key, msg_to_sign),
         }
-        .map_err(|_| anyhow!("signing failed"))
     }
+
+    /// Sign `msg_to_sign` as a 65-byte `r || s || v` ECDSA signature with a low `s`, as used by EVM chains
+    pub fn sign_recoverable(&self, msg_to_sign: &[u8; 32]) -> TofndResult<Vec<u8>> {
+        let key_pair = match self {
+            Self::Ecdsa(key_pair) => key_pair,
+            _ => {
+                return Err(anyhow!(
+                    "recoverable signatures are only supported for ECDSA"
+                ))
+            }
+        };
+
+        let signature = ecdsa::sign(key_pair.signing_key(), &message_digest(msg_to_sign)?)
+            .map_err(|_| anyhow!("signing failed"))?;
+        let signature = Signature::from_der(&signature)?;
+        let signature = signature.normalize_s().unwrap_or(signature);
+
+        // tofn doesn't return the recovery id, so find the one that recovers our key
+        let verifying_key = VerifyingKey::from_sec1_bytes(key_pair.encoded_verifying_key())?;
+        let recovery_id = [false, true]
+            .into_iter()
+            .map(|is_y_odd| RecoveryId::new(is_y_odd, false))
+            .find(|recovery_id| {
+                VerifyingKey::recover_from_prehash(msg_to_sign, &signature, *recovery_id)
+                    .is_ok_and(|recovered_key| recovered_key == verifying_key)
+            })
+            .ok_or_else(|| anyhow!("cannot compute the recovery id of the signature"))?;
+
+        let mut recoverable_signature = signature.to_bytes().to_vec();
+        recoverable_signature.push(RECOVERY_ID_OFFSET + recovery_id.to_byte());
+        Ok(recoverable_signature)
+    }
+}
+
+fn message_digest(msg_to_sign: &[u8; 32]) -> TofndResult<MessageDigest> {
+    Ok(msg_to_sign.as_slice().try_into()?)
+}
+
+/// Derive a BIP-340 key from `secret_recovery_key` and `session_nonce`.
+///
+/// The secret scalar is an ECDSA key of tofn's keygen for a separate session nonce: `0xff`
+/// followed by the SHA-256 of the domain and `session_nonce`. Key uids are UTF-8 strings, so no
+/// ECDSA or Ed25519 key uid can start with `0xff`, and every valid key uid fits in the nonce.
+fn schnorr_keygen(
+    secret_recovery_key: &SecretRecoveryKey,
+    session_nonce: &[u8],
+) -> TofndResult<schnorr::SigningKey> {
+    let domain_nonce: [u8; 32] = Sha256::new()
+        .chain_update(SCHNORR_DOMAIN)
+        .chain_update(session_nonce)
+        .finalize()
+        .into();
+
+    let key_pair = ecdsa::keygen(secret_recovery_key, &[&[0xff], &domain_nonce[..]].concat())
+        .map_err(|_| anyhow!("Cannot generate keypair"))?;
+
+    schnorr::SigningKey::from_bytes(&key_pair.signing_key().as_ref().to_bytes())
+        .map_err(|_| anyhow!("Cannot generate keypair"))
+}
+
+/// Sign `msg_to_sign` as a BIP-340 message
+fn schnorr_sign(signing_key: &schnorr::SigningKey, msg_to_sign: &[u8; 32]) -> TofndResult<Vec<u8>> {
+    // auxiliary randomness protects against side channels, see BIP-340
+    let mut aux_rand = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut aux_rand);
+
+    Ok(signing_key
+        .sign_raw(msg_to_sign, &aux_rand)
+        .map_err(|_| anyhow!("signing failed"))?
+        .to_bytes()
+        .to_vec())
 }
@@ -0,0 +1,345 @@
+//! Admission control for multisig requests.
+//!
+//! Every request re-derives keys from the stored mnemonics, which is CPU-heavy. The [Limiter]
+//! caps the number of requests that run concurrently and rate-limits each authenticated client with
+//! a token bucket. Without authentication, clients can't be told apart, since `party_uid` is chosen
+//! by the caller, so all of them share a single bucket. Requests over either limit are rejected
+//! with `ResourceExhausted` instead of queued.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+// logging
+use tracing::warn;
+
+/// at most this many clients are tracked; the least recently seen client is forgotten first
+const MAX_BUCKETS: usize = 1024;
+
+/// Why the [Limiter] rejected a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    TooManyConcurrentRequests,
+    RateLimitExceeded,
+    /// a batch has more entries than the burst size, so it can never be admitted
+    BatchTooLarge {
+        entries: usize,
+        burst: u32,
+    },
+}
+
+impl From<Rejection> for Status {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooManyConcurrentRequests => {
+                Status::resource_exhausted("too many concurrent requests")
+            }
+            Rejection::RateLimitExceeded => Status::resource_exhausted("rate limit exceeded"),
+            Rejection::BatchTooLarge { entries, burst } => Status::invalid_argument(format!(
+                "batch of {} entries exceeds the rate limit burst of {}",
+                entries, burst
+            )),
+        }
+    }
+}
+
+/// Token bucket parameters: `burst` requests at once, refilled at `per_second` requests per second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Limits applied to multisig requests; [None] disables a limit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// maximum number of requests handled concurrently
+    pub max_concurrent_requests: Option<usize>,
+    /// rate limit of each authenticated client, or of all unauthenticated clients together
+    pub rate_limit: Option<RateLimit>,
+    /// number of threads that derive keys and sign; [None] uses one thread per CPU
+    pub crypto_threads: Option<usize>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// position of the client in [Buckets::recent]
+    last_seen: u64,
+}
+
+impl TokenBucket {
+    fn full(rate_limit: &RateLimit, now: Instant, last_seen: u64) -> Self {
+        Self {
+            tokens: rate_limit.burst as f64,
+            last_refill: now,
+            last_seen,
+        }
+    }
+
+    fn refill(&mut self, rate_limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate_limit.per_second).min(rate_limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    fn try_take(&mut self, tokens: f64) -> bool {
+        if self.tokens < tokens {
+            return false;
+        }
+        self.tokens -= tokens;
+        true
+    }
+}
+
+/// Authentication label of a client; [None] for all unauthenticated clients
+type ClientKey = Option<String>;
+
+/// Token buckets of at most [MAX_BUCKETS] clients
+#[derive(Default)]
+struct Buckets {
+    by_client: HashMap<ClientKey, TokenBucket>,
+    /// clients by the [TokenBucket::last_seen] of their bucket, least recently seen first
+    recent: BTreeMap<u64, ClientKey>,
+    /// incremented every time a client is seen
+    counter: u64,
+}
+
+impl Buckets {
+    /// Returns the bucket of `client`, refilled up to `now`
+    fn get(
+        &mut self,
+        rate_limit: &RateLimit,
+        client: Option<&str>,
+        now: Instant,
+    ) -> &mut TokenBucket {
+        self.counter += 1;
+        let last_seen = self.counter;
+        let client = client.map(str::to_string);
+
+        match self.by_client.get_mut(&client) {
+            Some(bucket) => {
+                self.recent.remove(&bucket.last_seen);
+                bucket.last_seen = last_seen;
+            }
+            None => {
+                if self.by_client.len() >= MAX_BUCKETS {
+                    if let Some((_, oldest)) = self.recent.pop_first() {
+                        self.by_client.remove(&oldest);
+                    }
+                }
+                self.by_client.insert(
+                    client.clone(),
+                    TokenBucket::full(rate_limit, now, last_seen),
+                );
+            }
+        }
+        self.recent.insert(last_seen, client.clone());
+
+        let bucket = self
+            .by_client
+            .get_mut(&client)
+            .expect("bucket was just inserted");
+        bucket.refill(rate_limit, now);
+        bucket
+    }
+}
+
+/// Enforces [Limits]; clones share the same state
+#[derive(Clone)]
+pub struct Limiter {
+    concurrency: Option<Arc<Semaphore>>,
+    rate_limit: Option<RateLimit>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            concurrency: limits
+                .max_concurrent_requests
+                .map(|max| Arc::new(Semaphore::new(max))),
+            rate_limit: limits.rate_limit,
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    /// Admit a request of the client with authentication label `client`; unauthenticated requests
+    /// share a single rate limit. The returned permit must be held until the request completes.
+    pub fn admit(&self, client: Option<&str>) -> Result<Option<OwnedSemaphorePermit>, Rejection> {
+        self.admit_batch(client, 1)
+    }
+
+    /// Admit a batch of `requests` requests of `client`. A batch takes a single concurrency slot,
+    /// but is charged one token per request. Batches larger than the burst size are rejected.
+    /// Tokens are only taken from requests that get a concurrency slot.
+    pub fn admit_batch(
+        &self,
+        client: Option<&str>,
+        requests: usize,
+    ) -> Result<Option<OwnedSemaphorePermit>, Rejection> {
+        if let Some(rate_limit) = &self.rate_limit {
+            if requests > rate_limit.burst as usize {
+                warn!(
+                    "rejected batch of {} requests: larger than the rate limit burst",
+                    requests
+                );
+                return Err(Rejection::BatchTooLarge {
+                    entries: requests,
+                    burst: rate_limit.burst,
+                });
+            }
+        }
+
+        let permit = match &self.concurrency {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    warn!("rejected request: too many concurrent requests");
+                    return Err(Rejection::TooManyConcurrentRequests);
+                }
+            },
+            None => None,
+        };
+
+        if let Some(rate_limit) = &self.rate_limit {
+            if !self.take_tokens(rate_limit, client, requests) {
+                warn!(
+                    "[{}] rejected request: rate limit exceeded",
+                    client.unwrap_or("unauthenticated")
+                );
+                return Err(Rejection::RateLimitExceeded);
+            }
+        }
+
+        Ok(permit)
+    }
+
+    fn take_tokens(&self, rate_limit: &RateLimit, client: Option<&str>, requests: usize) -> bool {
+        self.buckets
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .get(rate_limit, client, Instant::now())
+            .try_take(requests as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_limit() {
+        let limiter = Limiter::new(Limits {
+            max_concurrent_requests: Some(2),
+            ..Default::default()
+        });
+
+        let first = limiter.admit(None).unwrap();
+        let _second = limiter.admit(None).unwrap();
+        assert_eq!(
+            limiter.admit(None).unwrap_err(),
+            Rejection::TooManyConcurrentRequests
+        );
+
+        // a completed request frees its slot
+        drop(first);
+        assert!(limiter.admit(None).is_ok());
+    }
+
+    #[test]
+    fn test_rejected_requests_keep_tokens() {
+        let limiter = Limiter::new(Limits {
+            max_concurrent_requests: Some(1),
+            rate_limit: Some(RateLimit {
+                per_second: 0.001,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+
+        // requests rejected for concurrency don't use up the rate limit
+        let permit = limiter.admit(Some("alice")).unwrap();
+        for _ in 0..5 {
+            assert_eq!(
+                limiter.admit(Some("alice")).unwrap_err(),
+                Rejection::TooManyConcurrentRequests
+            );
+        }
+        drop(permit);
+        assert!(limiter.admit(Some("alice")).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_cap() {
+        let rate_limit = RateLimit {
+            per_second: 0.001,
+            burst: 1,
+        };
+        let mut buckets = Buckets::default();
+        let now = Instant::now();
+
+        assert!(buckets.get(&rate_limit, Some("alice"), now).try_take(1.0));
+        for client in 0..MAX_BUCKETS {
+            assert!(buckets
+                .get(&rate_limit, Some(&client.to_string()), now)
+                .try_take(1.0));
+            // alice stays the most recently seen client
+            assert!(!buckets.get(&rate_limit, Some("alice"), now).try_take(1.0));
+        }
+        assert_eq!(buckets.by_client.len(), MAX_BUCKETS);
+        assert_eq!(buckets.recent.len(), MAX_BUCKETS);
+
+        // the least recently seen clients were forgotten
+        assert!(!buckets.by_client.contains_key(&Some("0".to_string())));
+        assert!(buckets.get(&rate_limit, Some("0"), now).try_take(1.0));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = Limiter::new(Limits {
+            rate_limit: Some(RateLimit {
+                per_second: 0.001,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+
+        assert!(limiter.admit(Some("alice")).is_ok());
+        assert!(limiter.admit(Some("alice")).is_ok());
+        assert_eq!(
+            limiter.admit(Some("alice")).unwrap_err(),
+            Rejection::RateLimitExceeded
+        );
+
+        // clients are limited independently; unauthenticated requests share a single limit
+        assert!(limiter.admit(Some("bob")).is_ok());
+        assert!(limiter.admit(None).is_ok());
+        assert!(limiter.admit(None).is_ok());
+        assert_eq!(
+            limiter.admit(None).unwrap_err(),
+            Rejection::RateLimitExceeded
+        );
+
+        // a batch is charged per request
+        assert!(limiter.admit_batch(Some("carol"), 2).is_ok());
+        assert!(limiter.admit(Some("carol")).is_err());
+        assert!(limiter.admit_batch(Some("dave"), 1).is_ok());
+        assert!(limiter.admit_batch(Some("dave"), 2).is_err());
+
+        // a batch larger than the burst size can never be admitted
+        assert_eq!(
+            limiter.admit_batch(Some("erin"), 3).unwrap_err(),
+            Rejection::BatchTooLarge {
+                entries: 3,
+                burst: 2
+            }
+        );
+        assert!(limiter.admit_batch(Some("erin"), 2).is_ok());
+    }
+}
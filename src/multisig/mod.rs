@@ -1,6 +1,14 @@
+mod batch_sign;
+mod cache;
+mod crypto_pool;
+mod error;
+mod hash;
+mod key_index;
 mod key_presence;
 mod keygen;
 mod keypair;
+pub mod limiter;
+pub mod policy;
 pub mod service;
 mod sign;
 
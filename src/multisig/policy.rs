@@ -0,0 +1,724 @@
+//! Signing policy of multisig requests.
+//!
+//! A policy is a TOML file of rules. Each keygen and sign request is checked against the rules in
+//! order, and the first rule that matches the request's rpc, client, key uid and algorithm
+//! decides. An `allow` rule can further restrict hash modes and the number of signatures of each
+//! key per time window. Requests that no rule matches get the `default` action.
+//!
+//! ```toml
+//! default = "deny"
+//!
+//! [[rules]]
+//! action = "allow"
+//! clients = ["vald"]
+//! key-uids = ["axelar-*"]
+//! algorithms = ["ecdsa"]
+//! hash-modes = ["raw"]
+//! max-signatures = 100
+//! window = 60
+//! ```
+//!
+//! Clients are identified by their authentication label. Without authentication, `clients` can't
+//! be enforced, because `party_uid` is set by the caller, so a policy with `clients` is rejected.
+//! Patterns may contain `*` wildcards. The file is reloaded on SIGHUP.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use super::error::{MultisigError, MultisigResult};
+use crate::{
+    proto::{Algorithm, HashMode},
+    TofndResult,
+};
+use anyhow::anyhow;
+
+// logging
+use tracing::{info, warn};
+
+/// at most this many signature windows are tracked; signatures of other keys are denied until
+/// one of them ends
+const MAX_SIGNATURE_WINDOWS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Allow,
+    #[default]
+    Deny,
+}
+
+/// rpcs that the policy applies to; a batch sign is checked as one sign per entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyRpc {
+    Keygen,
+    Sign,
+}
+
+/// Contents of a policy file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    default: Action,
+    #[serde(default)]
+    rules: Vec<RuleFile>,
+}
+
+/// A rule as written in the policy file; omitted keys match everything
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleFile {
+    action: Action,
+    rpcs: Option<Vec<PolicyRpc>>,
+    clients: Option<Vec<String>>,
+    key_uids: Option<Vec<String>>,
+    algorithms: Option<Vec<String>>,
+    hash_modes: Option<Vec<String>>,
+    max_signatures: Option<u32>,
+    /// length of the window of `max-signatures`, in seconds
+    window: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SignatureLimit {
+    max_signatures: u32,
+    window: Duration,
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: Action,
+    rpcs: Option<Vec<PolicyRpc>>,
+    clients: Option<Vec<String>>,
+    key_uids: Option<Vec<String>>,
+    algorithms: Option<Vec<Algorithm>>,
+    hash_modes: Option<Vec<HashMode>>,
+    signature_limit: Option<SignatureLimit>,
+    /// hash of the requests the rule matches, which identifies its signature windows
+    scope: u64,
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+/// A request to be checked against the policy
+pub struct PolicyRequest<'a> {
+    pub rpc: PolicyRpc,
+    pub client: &'a str,
+    pub key_uid: &'a str,
+    pub algorithm: i32,
+    /// hash mode of sign requests
+    pub hash_mode: Option<i32>,
+}
+
+/// (rule scope, key uid) of a signature window.
+/// Windows belong to the requests a rule matches rather than to its index, so that they are kept
+/// when a reload adds, removes or reorders rules.
+type WindowKey = (u64, String);
+
+/// Why [SignatureWindows::reserve] denied a signature
+#[derive(Debug, PartialEq)]
+enum WindowDenial {
+    LimitReached,
+    TooManyWindows,
+}
+
+struct SignatureWindow {
+    ends: Instant,
+    /// signatures made and in progress
+    signatures: u32,
+    /// tells apart windows of the same key
+    id: u64,
+}
+
+/// Signature windows of at most [MAX_SIGNATURE_WINDOWS] (rule scope, key uid) pairs
+#[derive(Default)]
+struct SignatureWindows {
+    by_key: HashMap<WindowKey, SignatureWindow>,
+    /// keys by the end and id of their window, the window that ends first first
+    by_end: BTreeMap<(Instant, u64), WindowKey>,
+    /// incremented for every new window
+    counter: u64,
+}
+
+impl SignatureWindows {
+    /// Reserve a signature of `key`; returns the id of its window. A new window is only opened
+    /// while fewer than [MAX_SIGNATURE_WINDOWS] are tracked, so that the limit can't be reset by
+    /// signing with many keys.
+    fn reserve(
+        &mut self,
+        key: WindowKey,
+        limit: SignatureLimit,
+        now: Instant,
+    ) -> Result<u64, WindowDenial> {
+        // expired windows are dropped in the order they end
+        while let Some(entry) = self.by_end.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            self.by_key.remove(&entry.remove());
+        }
+
+        if !self.by_key.contains_key(&key) {
+            if self.by_key.len() >= MAX_SIGNATURE_WINDOWS {
+                return Err(WindowDenial::TooManyWindows);
+            }
+
+            self.counter += 1;
+            let window = SignatureWindow {
+                ends: now + limit.window,
+                signatures: 0,
+                id: self.counter,
+            };
+            self.by_end.insert((window.ends, window.id), key.clone());
+            self.by_key.insert(key.clone(), window);
+        }
+
+        let window = self
+            .by_key
+            .get_mut(&key)
+            .ok_or(WindowDenial::LimitReached)?;
+        if window.signatures >= limit.max_signatures {
+            return Err(WindowDenial::LimitReached);
+        }
+        window.signatures += 1;
+        Ok(window.id)
+    }
+
+    /// Release a reservation of window `id` of `key`, unless the window has ended since
+    fn release(&mut self, key: &WindowKey, id: u64) {
+        if let Some(window) = self.by_key.get_mut(key) {
+            if window.id == id {
+                window.signatures = window.signatures.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// A request allowed by the [Policy]. A sign request under a signature limit holds a reservation
+/// in its window, which only counts once [Approval::signed] is called; dropping the approval
+/// releases it.
+#[must_use]
+pub struct Approval {
+    reservation: Option<Reservation>,
+}
+
+struct Reservation {
+    windows: Arc<Mutex<SignatureWindows>>,
+    key: WindowKey,
+    id: u64,
+}
+
+impl Approval {
+    fn unlimited() -> Self {
+        Self { reservation: None }
+    }
+
+    /// Count the signature of the approved request
+    pub fn signed(mut self) {
+        self.reservation = None;
+    }
+}
+
+impl Drop for Approval {
+    fn drop(&mut self) {
+        if let Some(reservation) = self.reservation.take() {
+            reservation
+                .windows
+                .lock()
+                .expect("policy lock poisoned")
+                .release(&reservation.key, reservation.id);
+        }
+    }
+}
+
+/// Checks requests against the rules of a policy file; clones share the same rules.
+/// A policy without a file allows every request.
+#[derive(Clone, Default)]
+pub struct Policy {
+    path: Option<PathBuf>,
+    /// whether clients are authenticated, so that `clients` rules can be enforced
+    auth: bool,
+    rules: Arc<RwLock<Option<Rules>>>,
+    /// signatures per (rule scope, key uid) in the current window
+    windows: Arc<Mutex<SignatureWindows>>,
+}
+
+impl Policy {
+    /// Load the policy at `path`; no path allows every request
+    pub fn load(path: Option<PathBuf>, auth: bool) -> TofndResult<Self> {
+        let rules = path
+            .as_deref()
+            .map(|path| Rules::load(path, auth))
+            .transpose()?;
+        if let Some(path) = &path {
+            info!("tofnd signing policy loaded from {:?}", path);
+        }
+
+        Ok(Self {
+            path,
+            auth,
+            rules: Arc::new(RwLock::new(rules)),
+            windows: Arc::new(Mutex::new(SignatureWindows::default())),
+        })
+    }
+
+    /// Re-read the policy file. On failure, the current rules are kept.
+    /// Signature counts are kept for the rules that match the same requests as before.
+    pub fn reload(&self) -> TofndResult<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let rules = Rules::load(path, self.auth)?;
+
+        *self.rules.write().expect("policy lock poisoned") = Some(rules);
+        info!("tofnd signing policy reloaded from {:?}", path);
+        Ok(())
+    }
+
+    /// Check `request` against the rules and log the decision; returns the reason of a denial as
+    /// an error. Call [Approval::signed] once an approved sign request succeeds.
+    pub fn check(&self, request: &PolicyRequest) -> MultisigResult<Approval> {
+        // without a policy file there is no decision to log
+        if self.path.is_none() {
+            return Ok(Approval::unlimited());
+        }
+
+        let decision = self.decide(request);
+        match &decision {
+            Ok(_) => info!(
+                "[{}] policy allows {:?} with key id [{}]",
+                request.client, request.rpc, request.key_uid
+            ),
+            Err(err) => warn!(
+                "[{}] policy denies {:?} with key id [{}]: {}",
+                request.client, request.rpc, request.key_uid, err
+            ),
+        }
+        decision
+    }
+
+    fn decide(&self, request: &PolicyRequest) -> MultisigResult<Approval> {
+        let rules = self.rules.read().expect("policy lock poisoned");
+        let rules = match rules.as_ref() {
+            Some(rules) => rules,
+            None => return Ok(Approval::unlimited()),
+        };
+
+        let (index, rule) = match rules
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(request))
+        {
+            Some(matching) => matching,
+            None => {
+                return match rules.default {
+                    Action::Allow => Ok(Approval::unlimited()),
+                    Action::Deny => Err(MultisigError::PolicyDenied(format!(
+                        "no rule allows {:?} of key {:?} by client {:?}",
+                        request.rpc, request.key_uid, request.client
+                    ))),
+                }
+            }
+        };
+
+        if rule.action == Action::Deny {
+            return Err(MultisigError::PolicyDenied(format!(
+                "rule {} denies {:?} of key {:?} by client {:?}",
+                index, request.rpc, request.key_uid, request.client
+            )));
+        }
+
+        if let (Some(hash_modes), Some(hash_mode)) = (&rule.hash_modes, request.hash_mode) {
+            if !hash_modes
+                .iter()
+                .any(|allowed| *allowed as i32 == hash_mode)
+            {
+                return Err(MultisigError::PolicyDenied(format!(
+                    "rule {} doesn't allow hash mode {} for key {:?}",
+                    index, hash_mode, request.key_uid
+                )));
+            }
+        }
+
+        let limit = match (rule.signature_limit, request.rpc) {
+            (Some(limit), PolicyRpc::Sign) => limit,
+            _ => return Ok(Approval::unlimited()),
+        };
+
+        let key = (rule.scope, request.key_uid.to_string());
+        let id = self
+            .windows
+            .lock()
+            .expect("policy lock poisoned")
+            .reserve(key.clone(), limit, Instant::now())
+            .map_err(|denial| {
+                MultisigError::PolicyDenied(match denial {
+                    WindowDenial::LimitReached => format!(
+                        "rule {} allows at most {} signatures of key {:?} per {:?}",
+                        index, limit.max_signatures, request.key_uid, limit.window
+                    ),
+                    WindowDenial::TooManyWindows => format!(
+                        "rule {} can't count signatures of key {:?}: the signatures of {} keys are already counted",
+                        index, request.key_uid, MAX_SIGNATURE_WINDOWS
+                    ),
+                })
+            })?;
+
+        Ok(Approval {
+            reservation: Some(Reservation {
+                windows: self.windows.clone(),
+                key,
+                id,
+            }),
+        })
+    }
+}
+
+impl Rules {
+    fn load(path: &Path, auth: bool) -> TofndResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("cannot read policy file {:?}: {}", path, err))?;
+        let rules = Self::parse(&contents)
+            .map_err(|err| anyhow!("invalid policy file {:?}: {}", path, err))?;
+
+        if !auth {
+            if let Some(index) = rules.rules.iter().position(|rule| rule.clients.is_some()) {
+                return Err(anyhow!(
+                    "invalid policy file {:?}: rule {} has clients, which require --auth; without it clients are identified by their party_uid, which any caller can set",
+                    path, index
+                ));
+            }
+        }
+
+        Ok(rules)
+    }
+
+    fn parse(contents: &str) -> TofndResult<Self> {
+        let file: PolicyFile = toml::from_str(contents)?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                Rule::try_from(rule).map_err(|err| anyhow!("rule {}: {}", index, err))
+            })
+            .collect::<TofndResult<_>>()?;
+
+        Ok(Self {
+            default: file.default,
+            rules,
+        })
+    }
+}
+
+impl TryFrom<RuleFile> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(rule: RuleFile) -> TofndResult<Self> {
+        let signature_limit = match (rule.max_signatures, rule.window) {
+            (Some(max_signatures), Some(window)) if window > 0 => Some(SignatureLimit {
+                max_signatures,
+                window: Duration::from_secs(window),
+            }),
+            (None, None) => None,
+            _ => return Err(anyhow!("max-signatures requires a positive window")),
+        };
+
+        let algorithms = rule
+            .algorithms
+            .map(|names| parse_names(&names, "ALGORITHM_", Algorithm::from_str_name))
+            .transpose()?;
+        let hash_modes = rule
+            .hash_modes
+            .map(|names| parse_names(&names, "HASH_MODE_", HashMode::from_str_name))
+            .transpose()?;
+
+        let mut hasher = DefaultHasher::new();
+        (
+            &rule.rpcs,
+            &rule.clients,
+            &rule.key_uids,
+            &algorithms,
+            &hash_modes,
+        )
+            .hash(&mut hasher);
+
+        Ok(Self {
+            action: rule.action,
+            rpcs: rule.rpcs,
+            clients: rule.clients,
+            key_uids: rule.key_uids,
+            algorithms,
+            hash_modes,
+            signature_limit,
+            scope: hasher.finish(),
+        })
+    }
+}
+
+impl Rule {
+    fn matches(&self, request: &PolicyRequest) -> bool {
+        any_of(&self.rpcs, |rpc| *rpc == request.rpc)
+            && any_of(&self.clients, |pattern| glob_match(pattern, request.client))
+            && any_of(&self.key_uids, |pattern| {
+                glob_match(pattern, request.key_uid)
+            })
+            && any_of(&self.algorithms, |algorithm| {
+                *algorithm as i32 == request.algorithm
+            })
+    }
+}
+
+/// Parse lowercase names of proto enum values, e.g. `ecdsa` for `ALGORITHM_ECDSA`
+fn parse_names<T>(
+    names: &[String],
+    prefix: &str,
+    from_str_name: fn(&str) -> Option<T>,
+) -> TofndResult<Vec<T>> {
+    names
+        .iter()
+        .map(|name| {
+            from_str_name(&format!("{}{}", prefix, name.to_uppercase()))
+                .ok_or_else(|| anyhow!("unknown value {:?}", name))
+        })
+        .collect()
+}
+
+/// Whether any of `values` satisfies `predicate`; omitted values match everything
+fn any_of<T>(values: &Option<Vec<T>>, predicate: impl FnMut(&T) -> bool) -> bool {
+    match values {
+        Some(values) => values.iter().any(predicate),
+        None => true,
+    }
+}
+
+/// Match `value` against `pattern`, where `*` matches any sequence of characters
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // no wildcard
+        None => return rest.is_empty(),
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    const POLICY: &str = r#"
+        default = "deny"
+
+        [[rules]]
+        action = "deny"
+        clients = ["mallory"]
+
+        [[rules]]
+        action = "allow"
+        rpcs = ["sign"]
+        clients = ["vald"]
+        key-uids = ["axelar-*"]
+        algorithms = ["ecdsa"]
+        hash-modes = ["raw", "keccak256"]
+        max-signatures = 2
+        window = 3600
+
+        [[rules]]
+        action = "allow"
+        rpcs = ["keygen"]
+    "#;
+
+    fn policy(contents: &str) -> Policy {
+        Policy {
+            path: Some(PathBuf::from("policy.toml")),
+            auth: true,
+            rules: Arc::new(RwLock::new(Some(Rules::parse(contents).unwrap()))),
+            windows: Arc::new(Mutex::new(SignatureWindows::default())),
+        }
+    }
+
+    fn sign<'a>(client: &'a str, key_uid: &'a str) -> PolicyRequest<'a> {
+        PolicyRequest {
+            rpc: PolicyRpc::Sign,
+            client,
+            key_uid,
+            algorithm: Algorithm::Ecdsa as i32,
+            hash_mode: Some(HashMode::Raw as i32),
+        }
+    }
+
+    fn reason(result: MultisigResult<Approval>) -> String {
+        match result {
+            Err(MultisigError::PolicyDenied(reason)) => reason,
+            Err(err) => panic!("expected a denial, got {:?}", err),
+            Ok(_) => panic!("expected a denial, got an approval"),
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = policy(POLICY);
+
+        assert!(policy.check(&sign("vald", "axelar-1")).is_ok());
+        assert!(reason(policy.check(&sign("mallory", "axelar-1"))).starts_with("rule 0"));
+        assert!(reason(policy.check(&sign("vald", "other"))).starts_with("no rule"));
+
+        let mut request = sign("vald", "axelar-1");
+        request.algorithm = Algorithm::Ed25519 as i32;
+        assert!(policy.check(&request).is_err());
+
+        let mut request = sign("vald", "axelar-1");
+        request.hash_mode = Some(HashMode::Sha256 as i32);
+        assert!(reason(policy.check(&request)).contains("hash mode"));
+
+        // keygen of any key is allowed, except for mallory
+        let mut request = sign("alice", "other");
+        request.rpc = PolicyRpc::Keygen;
+        request.hash_mode = None;
+        assert!(policy.check(&request).is_ok());
+    }
+
+    #[test]
+    fn test_signature_limit() {
+        let policy = policy(POLICY);
+
+        policy.check(&sign("vald", "axelar-1")).unwrap().signed();
+        // signatures in progress count towards the limit
+        let in_progress = policy.check(&sign("vald", "axelar-1")).unwrap();
+        assert!(reason(policy.check(&sign("vald", "axelar-1"))).contains("at most 2"));
+
+        // failed signatures don't
+        drop(in_progress);
+        policy.check(&sign("vald", "axelar-1")).unwrap().signed();
+        assert!(reason(policy.check(&sign("vald", "axelar-1"))).contains("at most 2"));
+
+        // keys are limited independently
+        assert!(policy.check(&sign("vald", "axelar-2")).is_ok());
+    }
+
+    #[test]
+    fn test_signature_window_cap() {
+        let limit = SignatureLimit {
+            max_signatures: 1,
+            window: Duration::from_secs(3600),
+        };
+        let mut windows = SignatureWindows::default();
+        let now = Instant::now();
+
+        for index in 0..MAX_SIGNATURE_WINDOWS {
+            let key = (0, index.to_string());
+            assert!(windows.reserve(key, limit, now).is_ok());
+        }
+
+        // new keys are denied instead of dropping the window of another key
+        let key = (0, MAX_SIGNATURE_WINDOWS.to_string());
+        assert_eq!(
+            windows.reserve(key.clone(), limit, now),
+            Err(WindowDenial::TooManyWindows)
+        );
+        assert_eq!(
+            windows.reserve((0, "0".to_string()), limit, now),
+            Err(WindowDenial::LimitReached)
+        );
+        assert_eq!(windows.by_key.len(), MAX_SIGNATURE_WINDOWS);
+        assert_eq!(windows.by_end.len(), MAX_SIGNATURE_WINDOWS);
+
+        // expired windows are dropped
+        let later = now + limit.window;
+        assert!(windows.reserve(key, limit, later).is_ok());
+        assert_eq!(windows.by_key.len(), 1);
+    }
+
+    #[test]
+    fn test_reload_keeps_signature_limit() {
+        let path = testdir!().join("policy.toml");
+        std::fs::write(&path, POLICY).unwrap();
+        let policy = Policy::load(Some(path.clone()), true).unwrap();
+
+        policy.check(&sign("vald", "axelar-1")).unwrap().signed();
+        policy.check(&sign("vald", "axelar-1")).unwrap().signed();
+        assert!(reason(policy.check(&sign("vald", "axelar-1"))).contains("at most 2"));
+
+        // the limited rule moves from index 1 to 2
+        let reordered = POLICY.replacen(
+            "[[rules]]",
+            "[[rules]]\naction = \"deny\"\nclients = [\"eve\"]\n\n[[rules]]",
+            1,
+        );
+        std::fs::write(&path, reordered).unwrap();
+        policy.reload().unwrap();
+        assert!(reason(policy.check(&sign("eve", "axelar-1"))).starts_with("rule 0"));
+        assert!(reason(policy.check(&sign("vald", "axelar-1"))).starts_with("rule 2 allows"));
+    }
+
+    #[test]
+    fn test_no_policy() {
+        let policy = Policy::load(None, false).unwrap();
+        assert!(policy.check(&sign("anyone", "any")).is_ok());
+        assert!(policy.reload().is_ok());
+    }
+
+    #[test]
+    fn test_clients_require_auth() {
+        let path = testdir!().join("policy.toml");
+        std::fs::write(&path, POLICY).unwrap();
+
+        assert!(Policy::load(Some(path.clone()), false).is_err());
+        assert!(Policy::load(Some(path), true).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        for contents in [
+            "default = \"maybe\"",
+            "[[rules]]\naction = \"allow\"\nalgorithms = [\"rsa\"]",
+            "[[rules]]\naction = \"allow\"\nmax-signatures = 1",
+            "[[rules]]\naction = \"allow\"\nunknown = 1",
+        ] {
+            assert!(Rules::parse(contents).is_err(), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("axelar-*", "axelar-1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exact-1"));
+        assert!(!glob_match("a*b", "ba"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+}
@@ -1,8 +1,21 @@
+use std::time::Instant;
+
 use tonic::Response;
 use tonic::Status;
 
+use crate::audit::{AuditEvent, AuditLog};
+use crate::auth::client_label;
 use crate::kv_manager::KvManager;
+use crate::metrics::{observe_request, Rpc};
+use crate::multisig::cache::{KeyPairCache, KEY_PAIR_CACHE_CAPACITY};
+use crate::multisig::crypto_pool::{request_deadline, CryptoPool};
+use crate::multisig::error::{MultisigError, MultisigResult};
+use crate::multisig::hash::message_digest;
+use crate::multisig::key_index::SeedKeys;
+use crate::multisig::limiter::{Limiter, Limits};
+use crate::multisig::policy::{Policy, PolicyRequest, PolicyRpc};
 use crate::proto;
+use crate::TofndResult;
 
 use tracing::{error, info};
 
@@ -10,12 +23,73 @@ use tracing::{error, info};
 #[derive(Clone)]
 pub struct MultisigService {
     pub(super) kv_manager: KvManager,
+    pub(super) key_pair_cache: KeyPairCache,
+    pub(super) seed_keys: SeedKeys,
+    pub(super) crypto_pool: CryptoPool,
+    limiter: Limiter,
+    pub(super) policy: Policy,
+    audit_log: AuditLog,
 }
 
+/// outcome of requests rejected by the [Limiter]. They were not processed, and are only counted in
+/// the metrics, so that clients can't flood the audit log
+const REJECTED: &str = "resource_exhausted";
+
 /// Create a new Multisig gRPC server
 impl MultisigService {
-    pub fn new(kv_manager: KvManager) -> Self {
-        Self { kv_manager }
+    pub fn new(kv_manager: KvManager, limits: Limits) -> TofndResult<Self> {
+        Ok(Self {
+            kv_manager,
+            key_pair_cache: KeyPairCache::new(KEY_PAIR_CACHE_CAPACITY),
+            seed_keys: SeedKeys::default(),
+            crypto_pool: CryptoPool::new(limits.crypto_threads)?,
+            limiter: Limiter::new(limits),
+            policy: Policy::default(),
+            audit_log: AuditLog::default(),
+        })
+    }
+
+    /// Check keygen and sign requests against `policy`; by default, every request is allowed
+    pub fn with_policy(self, policy: Policy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Record every request in `audit_log`; by default, requests are not recorded
+    pub fn with_audit_log(self, audit_log: AuditLog) -> Self {
+        Self { audit_log, ..self }
+    }
+
+    /// Record the outcome of a request that started at `started` in the metrics and the audit log.
+    /// Fails if the audit log is required and the record can't be written.
+    async fn observe(&self, event: &AuditEvent<'_>, started: Instant) -> MultisigResult<()> {
+        observe_request(event.rpc, event.algorithm, event.outcome, started);
+
+        match self.audit_log.record(event).await {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                error!(
+                    "[{}] cannot write audit record of {} with key id [{}]: {}",
+                    event.client,
+                    event.rpc.as_str(),
+                    event.key_uid,
+                    err
+                );
+                match self.audit_log.required() {
+                    true => Err(MultisigError::AuditErr(err.to_string())),
+                    // the request is already processed, so a failed write can only be reported
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// outcome of requests that failed with `err`; denials of the signing policy are counted
+/// separately
+pub(super) fn error_outcome(err: &MultisigError) -> &'static str {
+    match err {
+        MultisigError::PolicyDenied(_) => "denied",
+        _ => "error",
     }
 }
 
@@ -25,9 +99,26 @@ impl proto::multisig_server::Multisig for MultisigService {
         &self,
         request: tonic::Request<proto::KeyPresenceRequest>,
     ) -> Result<Response<proto::KeyPresenceResponse>, Status> {
+        let started = Instant::now();
+        let label = client_label(&request);
+        let deadline = request_deadline(request.metadata(), started);
         let request = request.into_inner();
+        let event = |outcome| AuditEvent {
+            rpc: Rpc::KeyPresence,
+            client: label.as_deref().unwrap_or_default(),
+            party_uid: "",
+            key_uid: &request.key_uid,
+            algorithm: request.algorithm,
+            digest: None,
+            pub_key: &request.pub_key,
+            outcome,
+        };
+
+        let _permit = self.limiter.admit(label.as_deref()).inspect_err(|_| {
+            observe_request(Rpc::KeyPresence, request.algorithm, REJECTED, started)
+        })?;
 
-        let response = match self.handle_key_presence(request).await {
+        let response = match self.handle_key_presence(&request, deadline).await {
             Ok(res) => {
                 info!("Key presence check completed succesfully");
                 res
@@ -37,6 +128,16 @@ impl proto::multisig_server::Multisig for MultisigService {
                 proto::key_presence_response::Response::Fail
             }
         };
+        self.observe(
+            &event(match response {
+                proto::key_presence_response::Response::Present => "present",
+                proto::key_presence_response::Response::Absent => "absent",
+                _ => "fail",
+            }),
+            started,
+        )
+        .await
+        .map_err(MultisigError::into_status)?;
 
         Ok(Response::new(proto::KeyPresenceResponse {
             response: response as i32,
@@ -47,58 +148,223 @@ impl proto::multisig_server::Multisig for MultisigService {
         &self,
         request: tonic::Request<proto::KeygenRequest>,
     ) -> Result<Response<proto::KeygenResponse>, Status> {
+        let started = Instant::now();
+        let label = client_label(&request);
+        let deadline = request_deadline(request.metadata(), started);
         let request = request.into_inner();
-        let result = match self.handle_keygen(&request).await {
+        // authenticated clients are logged by their token label
+        let client = label.clone().unwrap_or_else(|| request.party_uid.clone());
+        let event = |outcome, pub_key| AuditEvent {
+            rpc: Rpc::Keygen,
+            client: &client,
+            party_uid: &request.party_uid,
+            key_uid: &request.key_uid,
+            algorithm: request.algorithm,
+            digest: None,
+            pub_key,
+            outcome,
+        };
+
+        let _permit = self
+            .limiter
+            .admit(label.as_deref())
+            .inspect_err(|_| observe_request(Rpc::Keygen, request.algorithm, REJECTED, started))?;
+        let policy_request = PolicyRequest {
+            rpc: PolicyRpc::Keygen,
+            client: &client,
+            key_uid: &request.key_uid,
+            algorithm: request.algorithm,
+            hash_mode: None,
+        };
+        let result = match self.policy.check(&policy_request) {
+            Ok(_approval) => self.handle_keygen(&request, deadline).await,
+            Err(err) => Err(err),
+        };
+        match result {
             Ok(pub_key) => {
                 info!(
                     "[{}] Multisig Keygen with key id [{}] completed",
-                    request.party_uid, request.key_uid
+                    client, request.key_uid
                 );
-                proto::keygen_response::KeygenResponse::PubKey(pub_key)
+                self.observe(&event("pub_key", &pub_key), started)
+                    .await
+                    .map_err(MultisigError::into_status)?;
+
+                Ok(Response::new(proto::KeygenResponse {
+                    keygen_response: Some(proto::keygen_response::KeygenResponse::PubKey(pub_key)),
+                    error_reason: proto::ErrorReason::Unspecified.into(),
+                }))
             }
             Err(err) => {
                 error!(
-                    "[{}] Multisig Keygen with key id [{}] failed: {}",
-                    request.party_uid,
+                    "[{}] Multisig Keygen with key id [{}] failed ({}): {}",
+                    client,
                     request.key_uid,
-                    err.to_string()
+                    err.reason(),
+                    err
                 );
-                proto::keygen_response::KeygenResponse::Error(err.to_string())
-            }
-        };
+                self.observe(&event(error_outcome(&err), &[]), started)
+                    .await
+                    .map_err(MultisigError::into_status)?;
 
-        Ok(Response::new(proto::KeygenResponse {
-            keygen_response: Some(result),
-        }))
+                // requests denied by the policy are not processed
+                if let MultisigError::PolicyDenied(_) = err {
+                    return Err(err.into_status());
+                }
+                Ok(Response::new(proto::KeygenResponse {
+                    keygen_response: Some(proto::keygen_response::KeygenResponse::Error(
+                        err.to_string(),
+                    )),
+                    error_reason: err.error_reason().into(),
+                }))
+            }
+        }
     }
 
     async fn sign(
         &self,
         request: tonic::Request<proto::SignRequest>,
     ) -> Result<Response<proto::SignResponse>, Status> {
+        let started = Instant::now();
+        let label = client_label(&request);
+        let deadline = request_deadline(request.metadata(), started);
         let request = request.into_inner();
-        let result = match self.handle_sign(&request).await {
-            Ok(pub_key) => {
+        // authenticated clients are logged by their token label
+        let client = label.clone().unwrap_or_else(|| request.party_uid.clone());
+        let digest = message_digest(&request.msg_to_sign, request.hash_mode).ok();
+        let event = |outcome| AuditEvent {
+            rpc: Rpc::Sign,
+            client: &client,
+            party_uid: &request.party_uid,
+            key_uid: &request.key_uid,
+            algorithm: request.algorithm,
+            digest: digest.as_ref().map(|digest| &digest[..]),
+            pub_key: &request.pub_key,
+            outcome,
+        };
+
+        let _permit = self
+            .limiter
+            .admit(label.as_deref())
+            .inspect_err(|_| observe_request(Rpc::Sign, request.algorithm, REJECTED, started))?;
+        let policy_request = PolicyRequest {
+            rpc: PolicyRpc::Sign,
+            client: &client,
+            key_uid: &request.key_uid,
+            algorithm: request.algorithm,
+            hash_mode: Some(request.hash_mode),
+        };
+        let result = match self.policy.check(&policy_request) {
+            // only signatures that are made count towards the policy's signature limits
+            Ok(approval) => self
+                .handle_sign(&request, deadline)
+                .await
+                .inspect(|_| approval.signed()),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(signature) => {
                 info!(
                     "[{}] Multisig Sign with key id [{}] and message [{:?}] completed",
-                    request.party_uid, request.key_uid, request.msg_to_sign,
+                    client, request.key_uid, request.msg_to_sign,
                 );
-                proto::sign_response::SignResponse::Signature(pub_key)
+                // with a required audit log, a signature is only returned once it is recorded
+                self.observe(&event("signature"), started)
+                    .await
+                    .map_err(MultisigError::into_status)?;
+
+                Ok(Response::new(proto::SignResponse {
+                    sign_response: Some(proto::sign_response::SignResponse::Signature(signature)),
+                    error_reason: proto::ErrorReason::Unspecified.into(),
+                }))
             }
             Err(err) => {
                 error!(
-                    "[{}] Multisig sign with key id [{}] and message [{:?}] failed: {}",
-                    request.party_uid,
+                    "[{}] Multisig sign with key id [{}] and message [{:?}] failed ({}): {}",
+                    client,
                     request.key_uid,
                     request.msg_to_sign,
-                    err.to_string()
+                    err.reason(),
+                    err
+                );
+                self.observe(&event(error_outcome(&err)), started)
+                    .await
+                    .map_err(MultisigError::into_status)?;
+
+                // requests denied by the policy are not processed
+                if let MultisigError::PolicyDenied(_) = err {
+                    return Err(err.into_status());
+                }
+                Ok(Response::new(proto::SignResponse {
+                    sign_response: Some(proto::sign_response::SignResponse::Error(err.to_string())),
+                    error_reason: err.error_reason().into(),
+                }))
+            }
+        }
+    }
+
+    async fn batch_sign(
+        &self,
+        request: tonic::Request<proto::BatchSignRequest>,
+    ) -> Result<Response<proto::BatchSignResponse>, Status> {
+        let started = Instant::now();
+        let label = client_label(&request);
+        let deadline = request_deadline(request.metadata(), started);
+        let request = request.into_inner();
+        // authenticated clients are logged by their token label
+        let client = label.clone().unwrap_or_else(|| request.party_uid.clone());
+        let digests: Vec<_> = request
+            .entries
+            .iter()
+            .map(|entry| message_digest(&entry.msg_to_sign, entry.hash_mode).ok())
+            .collect();
+
+        let _permit = self
+            .limiter
+            .admit_batch(label.as_deref(), request.entries.len())
+            .inspect_err(|_| {
+                for entry in &request.entries {
+                    observe_request(Rpc::BatchSign, entry.algorithm, REJECTED, started);
+                }
+            })?;
+        let mut results = self.handle_batch_sign(&request, &client, deadline).await;
+
+        for (index, (entry, (result, outcome))) in
+            request.entries.iter().zip(&mut results).enumerate()
+        {
+            if let Some(proto::batch_sign_result::Outcome::Error(err)) = &result.outcome {
+                error!(
+                    "[{}] Multisig batch sign with key id [{}] and message [{:?}] failed ({}): {}",
+                    client,
+                    entry.key_uid,
+                    entry.msg_to_sign,
+                    err.reason().as_str_name(),
+                    err.message
                 );
-                proto::sign_response::SignResponse::Error(err.to_string())
             }
-        };
+            let event = AuditEvent {
+                rpc: Rpc::BatchSign,
+                client: &client,
+                party_uid: &request.party_uid,
+                key_uid: &entry.key_uid,
+                algorithm: entry.algorithm,
+                digest: digests[index].as_ref().map(|digest| &digest[..]),
+                pub_key: &entry.pub_key,
+                outcome,
+            };
+            // with a required audit log, a signature is only returned once it is recorded
+            if let Err(err) = self.observe(&event, started).await {
+                *result = proto::BatchSignResult::error(err.into());
+            }
+        }
+        info!(
+            "[{}] Multisig BatchSign of {} entries completed",
+            client,
+            results.len()
+        );
 
-        Ok(Response::new(proto::SignResponse {
-            sign_response: Some(result),
+        Ok(Response::new(proto::BatchSignResponse {
+            results: results.into_iter().map(|(result, _)| result).collect(),
         }))
     }
 }
@@ -1,68 +1,179 @@
-use super::{keypair::KeyPair, service::MultisigService};
+use super::{
+    error::{MultisigError, MultisigResult},
+    hash::message_digest,
+    keygen::check_key_uid,
+    keypair::KeyPair,
+    service::MultisigService,
+};
 use crate::{
-    proto::{Algorithm, SignRequest},
-    TofndResult,
+    kv_manager::KvManager,
+    mnemonic::StoredMnemonic,
+    proto::{Algorithm, SignRequest, SignatureFormat},
 };
-use anyhow::anyhow;
-use std::convert::TryInto;
-use tofn::sdk::api::SecretRecoveryKey;
+use std::{sync::Arc, time::Instant};
 
 impl MultisigService {
-    pub(super) async fn handle_sign(&self, request: &SignRequest) -> TofndResult<Vec<u8>> {
-        let algorithm = Algorithm::try_from(request.algorithm)
-            .map_err(|_| anyhow!("Invalid algorithm: {}", request.algorithm))?;
+    pub(super) async fn handle_sign(
+        &self,
+        request: &SignRequest,
+        deadline: Option<Instant>,
+    ) -> MultisigResult<Vec<u8>> {
+        let msg_to_sign = message_digest(&request.msg_to_sign, request.hash_mode)?;
+        let signature_format = signature_format(request.signature_format, request.algorithm)?;
 
         // re-generate secret key from seed, then sign
-        let secret_recovery_key = self
-            .find_matching_seed(&request.key_uid, &request.pub_key, algorithm)
+        let key_pair = self
+            .derive_key_pair(
+                &request.key_uid,
+                &request.pub_key,
+                request.algorithm,
+                deadline,
+            )
             .await?;
 
-        let key_pair = KeyPair::new(&secret_recovery_key, request.key_uid.as_bytes(), algorithm)
-            .map_err(|_| anyhow!("key re-generation failed"))?;
+        self.crypto_pool
+            .run(deadline, move || {
+                sign_with_format(&key_pair, &msg_to_sign, signature_format)
+            })
+            .await
+    }
 
-        let signature = key_pair
-            .sign(&request.msg_to_sign.as_slice().try_into()?)
-            .map_err(|_| anyhow!("sign failed"))?;
+    /// Re-generate the key pair of `key_uid` from the mnemonic that matches `pub_key`
+    pub(super) async fn derive_key_pair(
+        &self,
+        key_uid: &str,
+        pub_key: &[u8],
+        algorithm: i32,
+        deadline: Option<Instant>,
+    ) -> MultisigResult<Arc<KeyPair>> {
+        let algorithm = Algorithm::try_from(algorithm)
+            .map_err(|_| MultisigError::InvalidAlgorithm(algorithm))?;
+        check_key_uid(key_uid)?;
 
-        Ok(signature)
+        self.find_matching_key_pair(key_uid, pub_key, algorithm, deadline)
+            .await
     }
 
-    /// Given a `key_uid` and `pub_key`, find the matching mnemonic.
-    /// If `pub_key` is [None], use the currently active mnemonic.
-    pub(super) async fn find_matching_seed(
+    /// Derive the key pair of `key_uid` from `mnemonic` on the crypto pool
+    pub(super) async fn key_pair_from_mnemonic(
+        &self,
+        mnemonic: StoredMnemonic,
+        key_uid: &str,
+        algorithm: Algorithm,
+        deadline: Option<Instant>,
+    ) -> MultisigResult<KeyPair> {
+        let key_uid = key_uid.to_string();
+        self.crypto_pool
+            .run(deadline, move || {
+                let secret_recovery_key =
+                    KvManager::mnemonic_to_seed(mnemonic).map_err(anyhow::Error::from)?;
+                Ok(KeyPair::new(
+                    &secret_recovery_key,
+                    key_uid.as_bytes(),
+                    algorithm,
+                )?)
+            })
+            .await
+    }
+
+    /// Given a `key_uid` and `pub_key`, find the key pair of the matching mnemonic.
+    /// If `pub_key` is empty, use the currently active mnemonic.
+    pub(super) async fn find_matching_key_pair(
         &self,
         key_uid: &str,
         pub_key: &[u8],
         algorithm: Algorithm,
-    ) -> TofndResult<SecretRecoveryKey> {
-        if pub_key.is_empty() {
-            return self
-                .kv_manager
-                .seed()
-                .await
-                .map_err(|_| anyhow!("could not find current mnemonic"));
-        }
+        deadline: Option<Instant>,
+    ) -> MultisigResult<Arc<KeyPair>> {
+        // mnemonics can be rotated at runtime through the admin service
+        let _guard = self.kv_manager.mnemonic_lock().read().await;
 
-        let seed_key_iter = self
-            .kv_manager
-            .seed_key_iter()
-            .await
-            .map_err(|_e| anyhow!("could not iterate over mnemonic keys"))?;
+        let seed_key_iter = self.kv_manager.seed_key_iter().await.map_err(|err| {
+            MultisigError::KvErr(format!("could not iterate over mnemonic keys: {}", err))
+        })?;
+        let seed_count = seed_key_iter.len();
 
-        for seed_key in seed_key_iter {
-            let secret_recovery_key = self.kv_manager.get_seed(&seed_key).await?;
+        let seed_key_iter = match pub_key.is_empty() {
+            // the current mnemonic is the first one
+            true => seed_key_iter[..1].to_vec(),
+            // try the indexed mnemonic first, then every mnemonic from the latest one
+            false => {
+                let mut seed_keys = seed_key_iter;
+                if let Some(indexed_seed_key) = self
+                    .indexed_seed_key(key_uid, algorithm, pub_key, &seed_keys)
+                    .await
+                {
+                    if let Some(position) =
+                        seed_keys.iter().position(|key| *key == indexed_seed_key)
+                    {
+                        seed_keys[..=position].rotate_right(1);
+                    }
+                }
+                seed_keys
+            }
+        };
+
+        for seed_key in &seed_key_iter {
+            let key_pair = match self
+                .key_pair_cache
+                .get(key_uid, algorithm, seed_key, seed_count)
+            {
+                Some(key_pair) => key_pair,
+                None => {
+                    let mnemonic = self
+                        .kv_manager
+                        .get_mnemonic(seed_key)
+                        .await
+                        .map_err(|err| MultisigError::KvErr(err.to_string()))?;
+                    let key_pair = Arc::new(
+                        self.key_pair_from_mnemonic(mnemonic, key_uid, algorithm, deadline)
+                            .await?,
+                    );
 
-            let key_pair = KeyPair::new(&secret_recovery_key, key_uid.as_bytes(), algorithm)
-                .map_err(|_| anyhow!("key re-generation failed"))?;
+                    self.key_pair_cache.insert(
+                        key_uid,
+                        algorithm,
+                        seed_key,
+                        seed_count,
+                        key_pair.clone(),
+                    );
+                    key_pair
+                }
+            };
 
-            if pub_key == key_pair.encoded_verifying_key() {
-                return Ok(secret_recovery_key);
+            if pub_key.is_empty() || pub_key == key_pair.encoded_verifying_key() {
+                return Ok(key_pair);
             }
         }
 
-        Err(anyhow!(
-            "could not find a matching mnemonic for key {:?}",
-            key_uid
-        ))
+        Err(MultisigError::NoMatchingMnemonic(key_uid.to_string()))
+    }
+}
+
+/// Check that signatures of `algorithm` can be encoded as `signature_format`
+pub(super) fn signature_format(
+    signature_format: i32,
+    algorithm: i32,
+) -> MultisigResult<SignatureFormat> {
+    let signature_format = SignatureFormat::try_from(signature_format)
+        .map_err(|_| MultisigError::InvalidSignatureFormat(signature_format.to_string()))?;
+
+    if signature_format == SignatureFormat::Recoverable && algorithm != Algorithm::Ecdsa as i32 {
+        return Err(MultisigError::InvalidSignatureFormat(
+            "recoverable signatures are only supported for ECDSA".to_string(),
+        ));
     }
+
+    Ok(signature_format)
+}
+
+pub(super) fn sign_with_format(
+    key_pair: &KeyPair,
+    msg_to_sign: &[u8; 32],
+    signature_format: SignatureFormat,
+) -> MultisigResult<Vec<u8>> {
+    Ok(match signature_format {
+        SignatureFormat::Der => key_pair.sign(msg_to_sign)?,
+        SignatureFormat::Recoverable => key_pair.sign_recoverable(msg_to_sign)?,
+    })
 }
@@ -1,76 +1,137 @@
 use crate::{
     addr,
+    audit::AuditLog,
+    config::{TlsConfig, UnixSocketConfig},
+    drain,
     encrypted_sled::get_test_password,
     kv_manager::KvManager,
-    proto::Algorithm,
+    mnemonic::{Cmd, ExportMode, PhraseFormat},
+    proto::{Algorithm, ErrorReason},
     tests::{DEFAULT_TEST_IP, DEFAULT_TEST_PORT},
 };
 use tokio::{
     self,
-    net::TcpListener,
+    net::{TcpListener, UnixStream},
     sync::oneshot::{channel, Sender},
+    time::Duration,
 };
-use tokio_stream::wrappers::TcpListenerStream;
-use tonic::transport::Channel;
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::{
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri},
+    Code, Status,
+};
+use tonic_types::StatusExt;
 
-use super::service::MultisigService;
+use super::{
+    limiter::{Limits, RateLimit},
+    policy::Policy,
+    service::MultisigService,
+};
 
 use testdir::testdir;
-use tracing::error;
 use tracing_test::traced_test;
 
-use std::convert::TryInto;
+use std::{
+    convert::TryInto,
+    future::Future,
+    net::SocketAddr,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
 
 use crate::proto::{
-    key_presence_response::Response::Present, keygen_response::KeygenResponse,
-    multisig_client::MultisigClient, multisig_server::MultisigServer, sign_response::SignResponse,
-    KeyPresenceRequest, KeygenRequest, SignRequest,
+    batch_sign_result::Outcome,
+    key_presence_response::Response::{Absent, Present},
+    keygen_response::KeygenResponse,
+    multisig_client::MultisigClient,
+    multisig_server::MultisigServer,
+    sign_response::SignResponse,
+    BatchSignEntry, BatchSignRequest, HashMode, KeyPresenceRequest, KeygenRequest, SignRequest,
+    SignatureFormat,
 };
 
+// time given to in-flight requests after shutdown
+const TEST_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 // set up tests
 async fn spin_test_service_and_client() -> (MultisigClient<Channel>, Sender<()>) {
-    // create root directory for service
-    let root = testdir!();
+    let (server_addr, shutdown_sender) = spin_test_service(testdir!(), None).await;
 
-    // create a kv_manager
-    let kv_manager = KvManager::new(root, get_test_password())
-        .unwrap()
-        .handle_mnemonic(&crate::mnemonic::Cmd::Create)
+    // create a client to multisig service
+    let client = MultisigClient::connect(format!("http://{}", server_addr))
         .await
         .unwrap();
 
-    // create service
-    let service = MultisigServer::new(MultisigService::new(kv_manager));
+    // return the client and the shutdown channel for the service
+    (client, shutdown_sender)
+}
+
+// spin up a multisig service under `root`; returns the server's address and the shutdown channel
+async fn spin_test_service(root: PathBuf, tls: Option<TlsConfig>) -> (SocketAddr, Sender<()>) {
+    let service = MultisigService::new(test_kv_manager(root).await, Limits::default()).unwrap();
+    serve_test_service(service, tls).await
+}
+
+// create a kv_manager with a new mnemonic under `root`
+async fn test_kv_manager(root: PathBuf) -> KvManager {
+    KvManager::new(root, get_test_password())
+        .unwrap()
+        .with_export_mode(Some(ExportMode::Plaintext))
+        .handle_mnemonic(&Cmd::Create(PhraseFormat::default()))
+        .await
+        .unwrap()
+}
+
+// serve `service`; returns the server's address and the shutdown channel
+async fn serve_test_service(
+    service: MultisigService,
+    tls: Option<TlsConfig>,
+) -> (SocketAddr, Sender<()>) {
+    let (shutdown_sender, shutdown_receiver) = channel::<()>();
+    let server_addr = serve_test_service_until(service, tls, async {
+        shutdown_receiver.await.unwrap();
+    })
+    .await;
+    (server_addr, shutdown_sender)
+}
+
+// serve `service` until `shutdown` completes; returns the server's address
+async fn serve_test_service_until(
+    service: MultisigService,
+    tls: Option<TlsConfig>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> SocketAddr {
+    let service = MultisigServer::new(service);
 
     // create incoming tcp server for service
     let incoming = TcpListener::bind(addr(DEFAULT_TEST_IP, DEFAULT_TEST_PORT).unwrap())
         .await
         .unwrap();
 
-    // create shutdown channels
-    let (shutdown_sender, shutdown_receiver) = channel::<()>();
-
     // get server's address
     let server_addr = incoming.local_addr().unwrap();
 
-    // spin up multisig gRPC server with incoming shutdown
+    let mut server = tonic::transport::Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls.server_tls_config().unwrap()).unwrap();
+    }
+
+    // spin up multisig gRPC server with incoming shutdown; in-flight requests are drained on shutdown
     tokio::spawn(async move {
-        tonic::transport::Server::builder()
-            .add_service(service)
-            .serve_with_incoming_shutdown(TcpListenerStream::new(incoming), async {
-                shutdown_receiver.await.unwrap();
-            })
+        let (drain_sender, drain_receiver) = channel::<()>();
+        let server = server.add_service(service).serve_with_incoming_shutdown(
+            TcpListenerStream::new(incoming),
+            async {
+                shutdown.await;
+                drain_sender.send(()).unwrap();
+            },
+        );
+        drain(server, drain_receiver, TEST_GRACE_PERIOD)
             .await
             .unwrap();
     });
 
-    // create a client to multisig service
-    let client = MultisigClient::connect(format!("http://{}", server_addr))
-        .await
-        .unwrap();
-
-    // return the client and the shutdown channel for the service
-    (client, shutdown_sender)
+    server_addr
 }
 
 // dummy ctor for KeygenResult
@@ -93,6 +154,8 @@ impl SignRequest {
             party_uid: String::default(),
             pub_key: vec![],
             algorithm: algorithm as i32,
+            signature_format: SignatureFormat::Der as i32,
+            hash_mode: HashMode::Raw as i32,
         }
     }
 }
@@ -165,6 +228,156 @@ async fn test_multisig_ed25519_keygen_sign() {
     assert!(tofn::ed25519::verify(&to_array(pub_key), &msg_digest, &signature,).unwrap());
 }
 
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_schnorr_keygen_sign() {
+    let key = "multisig key";
+    let (mut client, shutdown_sender) = spin_test_service_and_client().await;
+
+    let request = KeygenRequest::new(key, Algorithm::Schnorr);
+
+    let response = client.keygen(request).await.unwrap().into_inner();
+    let pub_key = match response.keygen_response.unwrap() {
+        KeygenResponse::PubKey(pub_key) => pub_key,
+        KeygenResponse::Error(err) => {
+            panic!("Got error from keygen: {}", err);
+        }
+    };
+    assert_eq!(pub_key.len(), 32);
+
+    let request = SignRequest::new(key, Algorithm::Schnorr);
+    let msg_to_sign = request.msg_to_sign.clone();
+    let response = client.sign(request).await.unwrap().into_inner();
+    let signature = match response.sign_response.unwrap() {
+        SignResponse::Signature(signature) => signature,
+        SignResponse::Error(err) => {
+            panic!("Got error from sign: {}", err)
+        }
+    };
+    assert_eq!(signature.len(), 64);
+
+    // the x-only key is not the x coordinate of the ECDSA key of the same key uid
+    let response = client
+        .keygen(KeygenRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap()
+        .into_inner();
+    let ecdsa_pub_key = match response.keygen_response.unwrap() {
+        KeygenResponse::PubKey(pub_key) => pub_key,
+        KeygenResponse::Error(err) => {
+            panic!("Got error from keygen: {}", err);
+        }
+    };
+    assert_ne!(ecdsa_pub_key[1..], pub_key[..]);
+
+    shutdown_sender.send(()).unwrap();
+
+    let verifying_key = k256::schnorr::VerifyingKey::from_bytes(&pub_key).unwrap();
+    let signature = k256::schnorr::Signature::try_from(signature.as_slice()).unwrap();
+    assert!(verifying_key.verify_raw(&msg_to_sign, &signature).is_ok());
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_recoverable_sign() {
+    let key = "multisig key";
+    let (mut client, shutdown_sender) = spin_test_service_and_client().await;
+
+    let request = KeygenRequest::new(key, Algorithm::Ecdsa);
+    let response = client.keygen(request).await.unwrap().into_inner();
+    let pub_key = match response.keygen_response.unwrap() {
+        KeygenResponse::PubKey(pub_key) => pub_key,
+        KeygenResponse::Error(err) => {
+            panic!("Got error from keygen: {}", err);
+        }
+    };
+
+    for msg in 0..8 {
+        let mut request = SignRequest::new(key, Algorithm::Ecdsa);
+        request.msg_to_sign = vec![msg; 32];
+        request.signature_format = SignatureFormat::Recoverable as i32;
+        let msg_to_sign = request.msg_to_sign.clone();
+        let response = client.sign(request).await.unwrap().into_inner();
+        let signature = match response.sign_response.unwrap() {
+            SignResponse::Signature(signature) => signature,
+            SignResponse::Error(err) => {
+                panic!("Got error from sign: {}", err)
+            }
+        };
+        assert_eq!(signature.len(), 65);
+
+        let (signature, v) = signature.split_at(64);
+        let signature = k256::ecdsa::Signature::from_slice(signature).unwrap();
+        assert!(signature.normalize_s().is_none(), "s must be low");
+        let recovery_id = k256::ecdsa::RecoveryId::from_byte(v[0] - 27).unwrap();
+        let recovered_key =
+            k256::ecdsa::VerifyingKey::recover_from_prehash(&msg_to_sign, &signature, recovery_id)
+                .unwrap();
+
+        let verifying_key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&pub_key).unwrap();
+        assert_eq!(eth_address(&recovered_key), eth_address(&verifying_key));
+    }
+
+    // recoverable signatures are only defined for ECDSA
+    let mut request = SignRequest::new(key, Algorithm::Ed25519);
+    request.signature_format = SignatureFormat::Recoverable as i32;
+    let response = client.sign(request).await.unwrap().into_inner();
+    assert_sign_error(response, ErrorReason::InvalidSignatureFormat);
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_hash_modes() {
+    let key = "multisig key";
+    let (mut client, shutdown_sender) = spin_test_service_and_client().await;
+
+    let request = KeygenRequest::new(key, Algorithm::Ecdsa);
+    let response = client.keygen(request).await.unwrap().into_inner();
+    let pub_key = match response.keygen_response.unwrap() {
+        KeygenResponse::PubKey(pub_key) => pub_key,
+        KeygenResponse::Error(err) => {
+            panic!("Got error from keygen: {}", err);
+        }
+    };
+
+    // payloads of any length are hashed by tofnd
+    let payload = b"transfer 10 tokens".to_vec();
+    let mut request = SignRequest::new(key, Algorithm::Ecdsa);
+    request.msg_to_sign = payload.clone();
+    request.hash_mode = HashMode::Keccak256 as i32;
+    let response = client.sign(request).await.unwrap().into_inner();
+    let signature = match response.sign_response.unwrap() {
+        SignResponse::Signature(signature) => signature,
+        SignResponse::Error(err) => {
+            panic!("Got error from sign: {}", err)
+        }
+    };
+
+    let msg_digest = {
+        use sha3::{Digest, Keccak256};
+        Keccak256::digest(&payload).as_slice().try_into().unwrap()
+    };
+    assert!(tofn::ecdsa::verify(&to_array(pub_key), &msg_digest, &signature).unwrap());
+
+    let mut request = SignRequest::new(key, Algorithm::Ecdsa);
+    request.hash_mode = 42;
+    let response = client.sign(request).await.unwrap().into_inner();
+    assert_sign_error(response, ErrorReason::InvalidHashMode);
+
+    shutdown_sender.send(()).unwrap();
+}
+
+// Ethereum address of a secp256k1 public key
+fn eth_address(verifying_key: &k256::ecdsa::VerifyingKey) -> [u8; 20] {
+    use sha3::{Digest, Keccak256};
+
+    let uncompressed = verifying_key.to_encoded_point(false);
+    let hash = Keccak256::digest(&uncompressed.as_bytes()[1..]);
+    hash[12..].try_into().unwrap()
+}
+
 #[traced_test]
 #[tokio::test]
 async fn test_multisig_keygen_deterministic_and_unique_keys() {
@@ -173,7 +386,7 @@ async fn test_multisig_keygen_deterministic_and_unique_keys() {
 
     let mut seen_pub_keys = std::collections::HashSet::new();
 
-    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519] {
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
         let request = KeygenRequest::new(key, algorithm);
 
         let response = client.keygen(request.clone()).await.unwrap().into_inner();
@@ -206,7 +419,7 @@ async fn test_multisig_only_sign() {
     let key = "multisig key";
     let (mut client, shutdown_sender) = spin_test_service_and_client().await;
 
-    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519] {
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
         let request = SignRequest::new(key, algorithm);
         let response = client.sign(request).await.unwrap().into_inner();
         let _ = match response.sign_response.unwrap() {
@@ -222,32 +435,101 @@ async fn test_multisig_only_sign() {
 
 #[traced_test]
 #[tokio::test]
-async fn test_multisig_short_key_fail() {
-    let key = "k"; // too short key
+async fn test_multisig_batch_sign() {
     let (mut client, shutdown_sender) = spin_test_service_and_client().await;
 
+    let mut pub_keys = vec![];
     for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519] {
-        let keygen_request = KeygenRequest::new(key, algorithm);
-        let keygen_response = client.keygen(keygen_request).await.unwrap().into_inner();
+        let request = KeygenRequest::new("multisig key", algorithm);
+        let response = client.keygen(request).await.unwrap().into_inner();
+        match response.keygen_response.unwrap() {
+            KeygenResponse::PubKey(pub_key) => pub_keys.push(pub_key),
+            KeygenResponse::Error(err) => panic!("Got error from keygen: {}", err),
+        }
+    }
+
+    let entry = |algorithm: Algorithm, pub_key: &[u8], msg: u8| BatchSignEntry {
+        key_uid: "multisig key".to_string(),
+        msg_to_sign: vec![msg; 32],
+        pub_key: pub_key.to_vec(),
+        algorithm: algorithm as i32,
+        signature_format: SignatureFormat::Der as i32,
+        hash_mode: HashMode::Raw as i32,
+    };
+    let mut truncated = entry(Algorithm::Ecdsa, &pub_keys[0], 3);
+    truncated.msg_to_sign.pop();
+    let request = BatchSignRequest {
+        party_uid: String::default(),
+        entries: vec![
+            entry(Algorithm::Ecdsa, &pub_keys[0], 1),
+            entry(Algorithm::Ed25519, &pub_keys[1], 1),
+            truncated,
+            entry(Algorithm::Ecdsa, &pub_keys[0], 2),
+        ],
+    };
 
-        if let KeygenResponse::Error(err) = keygen_response.clone().keygen_response.unwrap() {
-            error!("{}", err);
+    let results = client
+        .batch_sign(request.clone())
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+    shutdown_sender.send(()).unwrap();
+
+    assert_eq!(results.len(), request.entries.len());
+    for (entry, result) in request.entries.iter().zip(results) {
+        let outcome = result.outcome.unwrap();
+        if entry.msg_to_sign.len() != 32 {
+            assert!(
+                matches!(outcome, Outcome::Error(err) if err.reason() == ErrorReason::InvalidDigestLength)
+            );
+            continue;
         }
-        assert!(matches!(
-            keygen_response.keygen_response.unwrap(),
-            KeygenResponse::Error(_)
-        ));
+
+        let signature = match outcome {
+            Outcome::Signature(signature) => signature,
+            Outcome::Error(err) => panic!("Got error from batch sign: {}", err.message),
+        };
+        let msg_digest = entry.msg_to_sign.as_slice().try_into().unwrap();
+        let verified = match Algorithm::try_from(entry.algorithm).unwrap() {
+            Algorithm::Ecdsa => {
+                tofn::ecdsa::verify(&to_array(entry.pub_key.clone()), &msg_digest, &signature)
+            }
+            Algorithm::Ed25519 => {
+                tofn::ed25519::verify(&to_array(entry.pub_key.clone()), &msg_digest, &signature)
+            }
+            Algorithm::Schnorr => unreachable!("no schnorr entries in the batch"),
+        };
+        assert!(verified.unwrap());
+    }
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_short_key_fail() {
+    let key = "k"; // too short key
+    let (mut client, shutdown_sender) = spin_test_service_and_client().await;
+
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
+        let keygen_request = KeygenRequest::new(key, algorithm);
+        let response = client.keygen(keygen_request).await.unwrap().into_inner();
+        assert_keygen_error(response, ErrorReason::KeyUidTooShort);
 
         let sign_request = SignRequest::new(key, algorithm);
-        let sign_response = client.sign(sign_request).await.unwrap().into_inner();
+        let response = client.sign(sign_request).await.unwrap().into_inner();
+        assert_sign_error(response, ErrorReason::KeyUidTooShort);
+    }
 
-        if let SignResponse::Error(err) = sign_response.clone().sign_response.unwrap() {
-            error!("{}", err);
-        }
-        assert!(matches!(
-            sign_response.sign_response.unwrap(),
-            SignResponse::Error(_)
-        ));
+    // tofn doesn't accept key uids longer than 256 bytes
+    let key = "k".repeat(257);
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
+        let keygen_request = KeygenRequest::new(&key, algorithm);
+        let response = client.keygen(keygen_request).await.unwrap().into_inner();
+        assert_keygen_error(response, ErrorReason::KeyUidTooLong);
+
+        let sign_request = SignRequest::new(&key, algorithm);
+        let response = client.sign(sign_request).await.unwrap().into_inner();
+        assert_sign_error(response, ErrorReason::KeyUidTooLong);
     }
 
     shutdown_sender.send(()).unwrap();
@@ -259,42 +541,496 @@ async fn test_multisig_truncated_msg_fail() {
     let key = "key-uid";
     let (mut client, shutdown_sender) = spin_test_service_and_client().await;
 
-    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519] {
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
         // attempt sign with truncated msg digest
         let mut request = SignRequest::new(key, algorithm);
         request.msg_to_sign = vec![32; 31];
         let response = client.sign(request.clone()).await.unwrap().into_inner();
-        if let SignResponse::Error(err) = response.clone().sign_response.unwrap() {
-            error!("{}", err);
-        }
-        assert!(matches!(
-            response.sign_response.unwrap(),
-            SignResponse::Error(_)
-        ));
+        assert_sign_error(response, ErrorReason::InvalidDigestLength);
     }
 
     shutdown_sender.send(()).unwrap();
 }
 
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_error_codes() {
+    let key = "multisig key";
+    let (mut client, shutdown_sender) = spin_test_service_and_client().await;
+
+    let mut request = KeygenRequest::new(key, Algorithm::Ecdsa);
+    request.algorithm = 42;
+    let response = client.keygen(request).await.unwrap().into_inner();
+    assert_keygen_error(response, ErrorReason::InvalidAlgorithm);
+
+    // no mnemonic derives this pub key
+    let mut request = SignRequest::new(key, Algorithm::Ecdsa);
+    request.pub_key = vec![2; 33];
+    let response = client.sign(request).await.unwrap().into_inner();
+    assert_sign_error(response, ErrorReason::NoMatchingMnemonic);
+
+    shutdown_sender.send(()).unwrap();
+}
+
+// check the reason of an error returned in a keygen response
+fn assert_keygen_error(response: crate::proto::KeygenResponse, reason: ErrorReason) {
+    assert!(matches!(
+        response.keygen_response,
+        Some(KeygenResponse::Error(_))
+    ));
+    assert_eq!(response.error_reason(), reason);
+}
+
+// check the reason of an error returned in a sign response
+fn assert_sign_error(response: crate::proto::SignResponse, reason: ErrorReason) {
+    assert!(matches!(
+        response.sign_response,
+        Some(SignResponse::Error(_))
+    ));
+    assert_eq!(response.error_reason(), reason);
+}
+
+// check the code and the `google.rpc.ErrorInfo` reason of a failed request
+fn assert_error(status: Status, code: Code, reason: &str) {
+    assert_eq!(status.code(), code);
+    let error_info = status.get_details_error_info().unwrap();
+    assert_eq!(error_info.reason, reason);
+    assert_eq!(error_info.domain, "tofnd");
+}
+
+// serve a multisig service with the signing policy in `contents`; returns a client, the policy
+// file, a handle to the service's policy and the shutdown channel
+async fn spin_policy_service(
+    contents: &str,
+) -> (MultisigClient<Channel>, PathBuf, Policy, Sender<()>) {
+    let dir = testdir!();
+    let policy_path = write_file(&dir, "policy.toml", contents);
+    let policy = Policy::load(Some(policy_path.clone()), false).unwrap();
+    let service = MultisigService::new(test_kv_manager(dir).await, Limits::default())
+        .unwrap()
+        .with_policy(policy.clone());
+    let (server_addr, shutdown_sender) = serve_test_service(service, None).await;
+
+    let client = MultisigClient::connect(format!("http://{}", server_addr))
+        .await
+        .unwrap();
+    (client, policy_path, policy, shutdown_sender)
+}
+
+const KEYGEN_ONLY_POLICY: &str = r#"
+    default = "deny"
+
+    [[rules]]
+    action = "allow"
+    rpcs = ["keygen"]
+"#;
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_policy_denied() {
+    let key = "multisig key";
+    let (mut client, _, _, shutdown_sender) = spin_policy_service(KEYGEN_ONLY_POLICY).await;
+
+    let response = client
+        .keygen(KeygenRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(matches!(
+        response.keygen_response,
+        Some(KeygenResponse::PubKey(_))
+    ));
+
+    let status = client
+        .sign(SignRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap_err();
+    assert_error(status, Code::PermissionDenied, "POLICY_DENIED");
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_policy_reload() {
+    let key = "multisig key";
+    let (mut client, policy_path, policy, shutdown_sender) =
+        spin_policy_service(KEYGEN_ONLY_POLICY).await;
+
+    client
+        .keygen(KeygenRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap();
+    let status = client
+        .sign(SignRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap_err();
+    assert_error(status, Code::PermissionDenied, "POLICY_DENIED");
+
+    std::fs::write(&policy_path, "default = \"allow\"").unwrap();
+    policy.reload().unwrap();
+
+    let response = client
+        .sign(SignRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(matches!(
+        response.sign_response,
+        Some(SignResponse::Signature(_))
+    ));
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_rate_limit_unauthenticated() {
+    let limits = Limits {
+        rate_limit: Some(RateLimit {
+            per_second: 0.001,
+            burst: 2,
+        }),
+        ..Default::default()
+    };
+    let service = MultisigService::new(test_kv_manager(testdir!()).await, limits).unwrap();
+    let (server_addr, shutdown_sender) = serve_test_service(service, None).await;
+    let mut client = MultisigClient::connect(format!("http://{}", server_addr))
+        .await
+        .unwrap();
+
+    // without authentication, changing party_uid doesn't escape the rate limit
+    for party_uid in ["alice", "bob"] {
+        let request = KeygenRequest {
+            party_uid: party_uid.to_string(),
+            ..KeygenRequest::new(party_uid, Algorithm::Ecdsa)
+        };
+        client.keygen(request).await.unwrap();
+    }
+
+    // key presence checks are limited as well
+    let status = client
+        .key_presence(KeyPresenceRequest {
+            key_uid: "alice".to_string(),
+            pub_key: vec![],
+            algorithm: Algorithm::Ecdsa as i32,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_audit_required() {
+    let key = "multisig key";
+    let root = testdir!();
+    let service = MultisigService::new(test_kv_manager(root.clone()).await, Limits::default())
+        .unwrap()
+        .with_audit_log(AuditLog::open(&root, true).unwrap());
+    let (server_addr, shutdown_sender) = serve_test_service(service, None).await;
+    let mut client = MultisigClient::connect(format!("http://{}", server_addr))
+        .await
+        .unwrap();
+
+    client
+        .keygen(KeygenRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap();
+
+    // the audit head can't be replaced by a directory, so no record can be completed
+    let head_path = root.join("audit.head");
+    std::fs::remove_file(&head_path).unwrap();
+    std::fs::create_dir(&head_path).unwrap();
+
+    // the signature is withheld
+    let status = client
+        .sign(SignRequest::new(key, Algorithm::Ecdsa))
+        .await
+        .unwrap_err();
+    assert_error(status, Code::Unavailable, "AUDIT_FAILURE");
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_sign_completes_after_shutdown() {
+    // with a single request at a time, other requests are rejected while sign is in flight
+    let kv_manager = test_kv_manager(testdir!()).await;
+    let limits = Limits {
+        max_concurrent_requests: Some(1),
+        ..Default::default()
+    };
+    let service = MultisigService::new(kv_manager.clone(), limits).unwrap();
+
+    // the server reports when it received the shutdown signal
+    let (shutdown_sender, shutdown_receiver) = channel::<()>();
+    let (shutdown_started_sender, shutdown_started) = channel::<()>();
+    let server_addr = serve_test_service_until(service, None, async {
+        shutdown_receiver.await.unwrap();
+        shutdown_started_sender.send(()).unwrap();
+    })
+    .await;
+    let url = format!("http://{}", server_addr);
+
+    // hold sign back until the server is shutting down
+    let mnemonic_guard = kv_manager.mnemonic_lock().write().await;
+
+    let mut client = MultisigClient::connect(url.clone()).await.unwrap();
+    let sign = tokio::spawn(async move {
+        client
+            .sign(SignRequest::new("multisig key", Algorithm::Ecdsa))
+            .await
+    });
+
+    // sign holds the only permit once other requests are rejected
+    let mut other_client = MultisigClient::connect(url).await.unwrap();
+    let key_presence = KeyPresenceRequest {
+        key_uid: "multisig key".to_string(),
+        pub_key: vec![],
+        algorithm: Algorithm::Ecdsa as i32,
+    };
+    let status = loop {
+        if let Err(status) = other_client.key_presence(key_presence.clone()).await {
+            break status;
+        }
+    };
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    drop(other_client);
+
+    shutdown_sender.send(()).unwrap();
+    shutdown_started.await.unwrap();
+
+    drop(mnemonic_guard);
+    let response = sign.await.unwrap().unwrap().into_inner();
+    assert!(matches!(
+        response.sign_response.unwrap(),
+        SignResponse::Signature(_)
+    ));
+}
+
 #[traced_test]
 #[tokio::test]
 async fn test_key_presence() {
     let (mut client, shutdown_sender) = spin_test_service_and_client().await;
 
-    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519] {
-        let presence_request = KeyPresenceRequest {
+    for algorithm in [Algorithm::Ecdsa, Algorithm::Ed25519, Algorithm::Schnorr] {
+        let presence_request = |pub_key: Vec<u8>| KeyPresenceRequest {
             key_uid: "key_uid".to_string(),
-            pub_key: vec![],
+            pub_key,
             algorithm: algorithm as i32,
         };
 
+        // the key wasn't generated yet
+        let response = client
+            .key_presence(presence_request(vec![]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.response, Absent as i32);
+
+        let response = client
+            .keygen(KeygenRequest::new("key_uid", algorithm))
+            .await
+            .unwrap()
+            .into_inner();
+        let pub_key = match response.keygen_response.unwrap() {
+            KeygenResponse::PubKey(pub_key) => pub_key,
+            KeygenResponse::Error(err) => panic!("Got error from keygen: {}", err),
+        };
+
+        for pub_key in [vec![], pub_key.clone()] {
+            let response = client
+                .key_presence(presence_request(pub_key))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.response, Present as i32);
+        }
+
+        // no mnemonic derives a different pub key
+        let mut other_pub_key = pub_key;
+        other_pub_key[1] ^= 1;
         let response = client
-            .key_presence(presence_request)
+            .key_presence(presence_request(other_pub_key))
             .await
             .unwrap()
             .into_inner();
-        assert_eq!(response.response, Present as i32);
+        assert_eq!(response.response, Absent as i32);
     }
 
     shutdown_sender.send(()).unwrap();
 }
+
+// generate a self-signed CA certificate
+fn generate_ca(name: &str) -> (rcgen::Certificate, rcgen::KeyPair) {
+    let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, name);
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = params.self_signed(&key).unwrap();
+    (cert, key)
+}
+
+// generate a PEM-encoded certificate and private key for `name`, signed by `ca`
+fn generate_signed_pem(
+    name: &str,
+    ca: &rcgen::Certificate,
+    ca_key: &rcgen::KeyPair,
+) -> (String, String) {
+    let params = rcgen::CertificateParams::new(vec![name.to_string()]).unwrap();
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = params.signed_by(&key, ca, ca_key).unwrap();
+    (cert.pem(), key.serialize_pem())
+}
+
+// connect to an https server at `server_addr` and attempt a keygen
+async fn tls_keygen(
+    server_addr: SocketAddr,
+    ca_pem: &str,
+    client_identity: Option<(String, String)>,
+) -> bool {
+    let mut tls = ClientTlsConfig::new()
+        .domain_name("localhost")
+        .ca_certificate(Certificate::from_pem(ca_pem));
+    if let Some((cert, key)) = client_identity {
+        tls = tls.identity(Identity::from_pem(cert, key));
+    }
+
+    let channel = Channel::from_shared(format!("https://127.0.0.1:{}", server_addr.port()))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap();
+
+    // with TLS 1.3 a rejected client certificate may only surface on the first request
+    let channel = match channel.connect().await {
+        Ok(channel) => channel,
+        Err(_) => return false,
+    };
+
+    MultisigClient::new(channel)
+        .keygen(KeygenRequest::new("multisig key", Algorithm::Ecdsa))
+        .await
+        .is_ok()
+}
+
+fn write_file(dir: &Path, name: &str, content: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_mutual_tls() {
+    let dir = testdir!();
+    let pki_dir = dir.join("pki");
+    std::fs::create_dir_all(&pki_dir).unwrap();
+
+    let (ca, ca_key) = generate_ca("tofnd test CA");
+    let (untrusted_ca, untrusted_ca_key) = generate_ca("untrusted CA");
+    let (server_cert, server_key) = generate_signed_pem("localhost", &ca, &ca_key);
+
+    let tls = TlsConfig {
+        cert: write_file(&pki_dir, "server.crt", &server_cert),
+        key: write_file(&pki_dir, "server.key", &server_key),
+        client_ca: Some(write_file(&pki_dir, "ca.crt", &ca.pem())),
+    };
+    let (server_addr, shutdown_sender) = spin_test_service(dir.join("tofnd"), Some(tls)).await;
+
+    // a client holding a certificate signed by the client CA is accepted
+    let client_identity = generate_signed_pem("vald", &ca, &ca_key);
+    assert!(tls_keygen(server_addr, &ca.pem(), Some(client_identity)).await);
+
+    // a client without a certificate is rejected
+    assert!(!tls_keygen(server_addr, &ca.pem(), None).await);
+
+    // a client with a certificate signed by an unknown CA is rejected
+    let untrusted_identity = generate_signed_pem("vald", &untrusted_ca, &untrusted_ca_key);
+    assert!(!tls_keygen(server_addr, &ca.pem(), Some(untrusted_identity)).await);
+
+    shutdown_sender.send(()).unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_multisig_unix_socket() {
+    let dir = testdir!();
+    let unix_socket = UnixSocketConfig {
+        path: dir.join("tofnd.sock"),
+        mode: 0o660,
+        uid: None,
+        gid: None,
+    };
+
+    let kv_manager = KvManager::new(dir.join("tofnd"), get_test_password())
+        .unwrap()
+        .with_export_mode(Some(ExportMode::Plaintext))
+        .handle_mnemonic(&Cmd::Create(PhraseFormat::default()))
+        .await
+        .unwrap();
+    let service = MultisigServer::new(MultisigService::new(kv_manager, Limits::default()).unwrap());
+
+    let (incoming, socket_file) = unix_socket.bind().unwrap();
+    let mode = std::fs::metadata(&unix_socket.path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    let (shutdown_sender, shutdown_receiver) = channel::<()>();
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming_shutdown(UnixListenerStream::new(incoming), async {
+                shutdown_receiver.await.unwrap();
+            })
+            .await
+            .unwrap();
+    });
+
+    // the uri is ignored; every connection goes to the unix socket
+    let socket_path = unix_socket.path.clone();
+    let channel = Endpoint::try_from("http://[::]:50051")
+        .unwrap()
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let socket_path = socket_path.clone();
+            async move {
+                Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(
+                    UnixStream::connect(socket_path).await?,
+                ))
+            }
+        }))
+        .await
+        .unwrap();
+
+    let response = MultisigClient::new(channel)
+        .keygen(KeygenRequest::new("multisig key", Algorithm::Ecdsa))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(matches!(
+        response.keygen_response.unwrap(),
+        KeygenResponse::PubKey(_)
+    ));
+
+    shutdown_sender.send(()).unwrap();
+
+    // the socket file is removed once it is no longer served
+    drop(socket_file);
+    assert!(!unix_socket.path.exists());
+
+    // a regular file is never replaced by the socket
+    let file_path = dir.join("not-a-socket");
+    std::fs::write(&file_path, "").unwrap();
+    let unix_socket = UnixSocketConfig {
+        path: file_path,
+        ..unix_socket
+    };
+    assert!(unix_socket.bind().is_err());
+}
@@ -1,6 +1,6 @@
 //! mnemonic tests at the TofndParty level
 
-use crate::mnemonic::Cmd;
+use crate::mnemonic::{Cmd, PhraseFormat};
 use testdir::testdir;
 
 use super::{tofnd_party::TofndParty, InitParty};
@@ -25,7 +25,7 @@ async fn mnemonic_create() {
     // dummy init data
     let init_party = dummy_init_party();
     // Create should succeed
-    let _ = TofndParty::new(init_party, Cmd::Create, &dir).await;
+    let _ = TofndParty::new(init_party, Cmd::Create(PhraseFormat::default()), &dir).await;
 }
 
 #[should_panic]
@@ -35,5 +35,5 @@ async fn mnemonic_export_panic() {
     // dummy init data
     let init_party = dummy_init_party();
     // Export should fail
-    let _ = TofndParty::new(init_party, Cmd::Export, &dir).await;
+    let _ = TofndParty::new(init_party, Cmd::Export(None), &dir).await;
 }
@@ -4,8 +4,8 @@ use crate::{
     config::Config,
     encrypted_sled::{get_test_password, PasswordMethod},
     kv_manager::KvManager,
-    mnemonic::Cmd,
-    multisig::service::MultisigService,
+    mnemonic::{Cmd, ExportMode},
+    multisig::{limiter::Limits, service::MultisigService},
     proto::{self, multisig_server::MultisigServer},
     tests::SLEEP_TIME,
 };
@@ -47,10 +47,25 @@ impl TofndParty {
 
         let cfg = Config {
             mnemonic_cmd,
+            plaintext_export: true,
             ip: server_ip.to_string(),
             port: server_port,
             tofnd_path,
             password_method: PasswordMethod::NoPassword,
+            tls: None,
+            unix_socket: None,
+            metrics_addr: None,
+            shutdown_grace_period: Duration::from_secs(SLEEP_TIME),
+            auth: false,
+            auth_tokens: vec![],
+            token_cmd: None,
+            index_rebuild: None,
+            audit_verify: false,
+            audit_required: false,
+            limits: Limits::default(),
+            policy: None,
+            admin: None,
+            print_config: false,
         };
 
         // start service
@@ -73,9 +88,13 @@ impl TofndParty {
                 panic!("could not start kv manager");
             }
         };
-        let kv_manager = kv_manager.handle_mnemonic(&cfg.mnemonic_cmd).await.unwrap();
+        let kv_manager = kv_manager
+            .with_export_mode(Some(ExportMode::Plaintext))
+            .handle_mnemonic(&cfg.mnemonic_cmd)
+            .await
+            .unwrap();
 
-        let service = MultisigServer::new(MultisigService::new(kv_manager));
+        let service = MultisigServer::new(MultisigService::new(kv_manager, cfg.limits).unwrap());
 
         // let (startup_sender, startup_receiver) = tokio::sync::oneshot::channel::<()>();
         let server_handle = tokio::spawn(async move {